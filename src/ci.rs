@@ -0,0 +1,407 @@
+//! MIDI Capability Inquiry (MIDI-CI) discovery and profile configuration
+//! CI messages are Universal Non-Realtime sysex with sub-id #1 0x0D, each carrying the source and destination MUIDs
+
+use crate::universal::{push, read_7bit, write_7bit, DeviceIdentity, ManufacturerId, UniversalHeader};
+use crate::{MidiError, U7};
+use heapless::Vec;
+
+pub const SUB_ID_CI: u8 = 0x0D;
+
+/// CI message format version 1.1
+pub const CI_VERSION: u8 = 0x01;
+
+/// Device id addressing the whole port (function block) instead of a single channel
+pub const TO_PORT: U7 = U7(0x7F);
+
+/// Maximum number of profiles in a Reply to Profile Inquiry
+pub const MAX_PROFILES: usize = 8;
+
+/// Capability Inquiry categories supported, as advertised in discovery
+pub const CATEGORY_PROFILE_CONFIGURATION: u8 = 0x04;
+pub const CATEGORY_PROPERTY_EXCHANGE: u8 = 0x08;
+
+const DISCOVERY: u8 = 0x70;
+const DISCOVERY_REPLY: u8 = 0x71;
+const INVALIDATE_MUID: u8 = 0x7E;
+const NAK: u8 = 0x7F;
+const PROFILE_INQUIRY: u8 = 0x20;
+const PROFILE_INQUIRY_REPLY: u8 = 0x21;
+const SET_PROFILE_ON: u8 = 0x22;
+const SET_PROFILE_OFF: u8 = 0x23;
+const PROFILE_ENABLED: u8 = 0x24;
+const PROFILE_DISABLED: u8 = 0x25;
+const PE_CAPABILITIES: u8 = 0x30;
+const PE_CAPABILITIES_REPLY: u8 = 0x31;
+
+/// 28-bit MIDI Unique Identifier, randomly chosen by each device
+pub type Muid = u32;
+
+/// MUID addressing every device
+pub const BROADCAST_MUID: Muid = 0x0FFF_FFFF;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProfileId(pub [u8; 5]);
+
+/// Addressing common to all CI messages
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CiHeader {
+    /// Channel 0-15, or TO_PORT
+    pub device_id: U7,
+    pub version: u8,
+    pub source: Muid,
+    pub destination: Muid,
+}
+
+impl CiHeader {
+    pub fn new(source: Muid, destination: Muid) -> Self {
+        CiHeader { device_id: TO_PORT, version: CI_VERSION, source, destination }
+    }
+
+    /// Header of a reply to a message with this header
+    pub fn reply(&self, source: Muid) -> Self {
+        CiHeader { device_id: self.device_id, version: CI_VERSION, source, destination: self.source }
+    }
+}
+
+/// Contents of Discovery and Reply to Discovery
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Discovery {
+    pub identity: DeviceIdentity,
+    /// Bitmap of CATEGORY_ flags
+    pub category: u8,
+    /// Largest sysex message the device can receive
+    pub max_sysex: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CiMessage {
+    Discovery(Discovery),
+    DiscoveryReply(Discovery),
+    /// MUID to be discarded, usually because of a collision
+    InvalidateMuid(Muid),
+    Nak,
+    ProfileInquiry,
+    ProfileInquiryReply {
+        enabled: Vec<ProfileId, MAX_PROFILES>,
+        disabled: Vec<ProfileId, MAX_PROFILES>,
+    },
+    SetProfileOn(ProfileId),
+    SetProfileOff(ProfileId),
+    ProfileEnabled(ProfileId),
+    ProfileDisabled(ProfileId),
+    /// Property Exchange capabilities inquiry and reply, with number of simultaneous requests supported
+    PeCapabilities(u8),
+    PeCapabilitiesReply(u8),
+    /// Any other CI message, identified by its sub-id #2
+    Other(u8),
+}
+
+impl CiMessage {
+    fn sub_id2(&self) -> u8 {
+        match self {
+            CiMessage::Discovery(_) => DISCOVERY,
+            CiMessage::DiscoveryReply(_) => DISCOVERY_REPLY,
+            CiMessage::InvalidateMuid(_) => INVALIDATE_MUID,
+            CiMessage::Nak => NAK,
+            CiMessage::ProfileInquiry => PROFILE_INQUIRY,
+            CiMessage::ProfileInquiryReply { .. } => PROFILE_INQUIRY_REPLY,
+            CiMessage::SetProfileOn(_) => SET_PROFILE_ON,
+            CiMessage::SetProfileOff(_) => SET_PROFILE_OFF,
+            CiMessage::ProfileEnabled(_) => PROFILE_ENABLED,
+            CiMessage::ProfileDisabled(_) => PROFILE_DISABLED,
+            CiMessage::PeCapabilities(_) => PE_CAPABILITIES,
+            CiMessage::PeCapabilitiesReply(_) => PE_CAPABILITIES_REPLY,
+            CiMessage::Other(sub_id2) => *sub_id2,
+        }
+    }
+
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END
+    /// Returns Err(InvalidSysex) if body is not a CI message
+    pub fn decode(body: &[u8]) -> Result<(CiHeader, CiMessage), MidiError> {
+        let (header, sub_id2, data) = read_header(body)?;
+        let message = match sub_id2 {
+            DISCOVERY => CiMessage::Discovery(read_discovery(data)?),
+            DISCOVERY_REPLY => CiMessage::DiscoveryReply(read_discovery(data)?),
+            INVALIDATE_MUID => CiMessage::InvalidateMuid(read_7bit(data, 4)?.0),
+            NAK => CiMessage::Nak,
+            PROFILE_INQUIRY => CiMessage::ProfileInquiry,
+            PROFILE_INQUIRY_REPLY => {
+                let (enabled, data) = read_profiles(data)?;
+                let (disabled, _) = read_profiles(data)?;
+                CiMessage::ProfileInquiryReply { enabled, disabled }
+            }
+            SET_PROFILE_ON => CiMessage::SetProfileOn(read_profile(data)?.0),
+            SET_PROFILE_OFF => CiMessage::SetProfileOff(read_profile(data)?.0),
+            PROFILE_ENABLED => CiMessage::ProfileEnabled(read_profile(data)?.0),
+            PROFILE_DISABLED => CiMessage::ProfileDisabled(read_profile(data)?.0),
+            PE_CAPABILITIES => CiMessage::PeCapabilities(*data.first().ok_or(MidiError::TruncatedMessage)?),
+            PE_CAPABILITIES_REPLY => CiMessage::PeCapabilitiesReply(*data.first().ok_or(MidiError::TruncatedMessage)?),
+            other => CiMessage::Other(other),
+        };
+        Ok((header, message))
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    /// Other messages are encoded without payload
+    pub fn encode<const N: usize>(&self, header: &CiHeader, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        write_header(header, self.sub_id2(), buf)?;
+        match self {
+            CiMessage::Discovery(discovery) | CiMessage::DiscoveryReply(discovery) => {
+                push(buf, &discovery.identity.manufacturer.to_bytes())?;
+                discovery.identity.write_details(buf)?;
+                push(buf, &[discovery.category])?;
+                write_7bit(buf, discovery.max_sysex, 4)
+            }
+            CiMessage::InvalidateMuid(muid) => write_7bit(buf, *muid, 4),
+            CiMessage::ProfileInquiryReply { enabled, disabled } => {
+                write_profiles(buf, enabled)?;
+                write_profiles(buf, disabled)
+            }
+            CiMessage::SetProfileOn(profile) | CiMessage::SetProfileOff(profile)
+            | CiMessage::ProfileEnabled(profile) | CiMessage::ProfileDisabled(profile) => push(buf, &profile.0),
+            CiMessage::PeCapabilities(requests) | CiMessage::PeCapabilitiesReply(requests) => push(buf, &[*requests]),
+            CiMessage::Nak | CiMessage::ProfileInquiry | CiMessage::Other(_) => Ok(()),
+        }
+    }
+}
+
+/// Read CI header from sysex body, returns header, sub-id #2 and message data
+/// Returns Err(InvalidSysex) if body is not a CI message
+pub(crate) fn read_header(body: &[u8]) -> Result<(CiHeader, u8, &[u8]), MidiError> {
+    let (universal, data) = UniversalHeader::read(body)?;
+    if universal.realtime || universal.sub_id1 != SUB_ID_CI {
+        return Err(MidiError::InvalidSysex);
+    }
+    let (&version, data) = data.split_first().ok_or(MidiError::TruncatedMessage)?;
+    let (source, data) = read_7bit(data, 4)?;
+    let (destination, data) = read_7bit(data, 4)?;
+    Ok((CiHeader { device_id: universal.device_id, version, source, destination }, universal.sub_id2, data))
+}
+
+pub(crate) fn write_header<const N: usize>(header: &CiHeader, sub_id2: u8, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+    UniversalHeader::non_realtime(header.device_id, SUB_ID_CI, sub_id2).write(buf)?;
+    push(buf, &[header.version])?;
+    write_7bit(buf, header.source, 4)?;
+    write_7bit(buf, header.destination, 4)
+}
+
+fn read_discovery(data: &[u8]) -> Result<Discovery, MidiError> {
+    let manufacturer = data.get(..3).ok_or(MidiError::TruncatedMessage)?;
+    let manufacturer = ManufacturerId::from_bytes([manufacturer[0], manufacturer[1], manufacturer[2]]);
+    let (identity, data) = DeviceIdentity::read_details(manufacturer, &data[3..])?;
+    let (&category, data) = data.split_first().ok_or(MidiError::TruncatedMessage)?;
+    let (max_sysex, _) = read_7bit(data, 4)?;
+    Ok(Discovery { identity, category, max_sysex })
+}
+
+fn read_profile(data: &[u8]) -> Result<(ProfileId, &[u8]), MidiError> {
+    let id = data.get(..5).ok_or(MidiError::TruncatedMessage)?;
+    Ok((ProfileId([id[0], id[1], id[2], id[3], id[4]]), &data[5..]))
+}
+
+fn read_profiles(data: &[u8]) -> Result<(Vec<ProfileId, MAX_PROFILES>, &[u8]), MidiError> {
+    let (count, mut data) = read_7bit(data, 2)?;
+    let mut profiles = Vec::new();
+    for _ in 0..count {
+        let (profile, rest) = read_profile(data)?;
+        profiles.push(profile).map_err(|_| MidiError::SysexOutOfBounds)?;
+        data = rest;
+    }
+    Ok((profiles, data))
+}
+
+fn write_profiles<const N: usize>(buf: &mut Vec<u8, N>, profiles: &[ProfileId]) -> Result<(), MidiError> {
+    write_7bit(buf, profiles.len() as u32, 2)?;
+    for profile in profiles {
+        push(buf, &profile.0)?;
+    }
+    Ok(())
+}
+
+/// Answers discovery and profile configuration on behalf of this device
+#[derive(Debug)]
+pub struct CiResponder {
+    muid: Muid,
+    invalidated: bool,
+    discovery: Discovery,
+    /// Supported profiles, and whether they are enabled
+    profiles: Vec<(ProfileId, bool), MAX_PROFILES>,
+    /// Simultaneous Property Exchange requests supported, if any
+    pe_requests: Option<u8>,
+}
+
+impl CiResponder {
+    /// `muid` should be randomly generated at startup
+    pub fn new(muid: Muid, identity: DeviceIdentity) -> Self {
+        CiResponder {
+            muid: muid & BROADCAST_MUID,
+            invalidated: false,
+            discovery: Discovery {
+                identity,
+                category: CATEGORY_PROFILE_CONFIGURATION,
+                max_sysex: 128,
+            },
+            profiles: Vec::new(),
+            pe_requests: None,
+        }
+    }
+
+    pub fn with_max_sysex(mut self, max_sysex: u32) -> Self {
+        self.discovery.max_sysex = max_sysex;
+        self
+    }
+
+    pub fn with_category(mut self, category: u8) -> Self {
+        self.discovery.category = category;
+        self
+    }
+
+    /// Add a supported profile, ignored if MAX_PROFILES are already supported
+    pub fn with_profile(mut self, profile: ProfileId, enabled: bool) -> Self {
+        let _ = self.profiles.push((profile, enabled));
+        self
+    }
+
+    /// Advertise Property Exchange support, with the number of simultaneous requests supported
+    pub fn with_property_exchange(mut self, requests: u8) -> Self {
+        self.discovery.category |= CATEGORY_PROPERTY_EXCHANGE;
+        self.pe_requests = Some(requests);
+        self
+    }
+
+    pub fn muid(&self) -> Muid {
+        self.muid
+    }
+
+    /// Returns true if another device invalidated our MUID
+    /// The responder stays silent until a new MUID is set
+    pub fn is_invalidated(&self) -> bool {
+        self.invalidated
+    }
+
+    pub fn set_muid(&mut self, muid: Muid) {
+        self.muid = muid & BROADCAST_MUID;
+        self.invalidated = false;
+    }
+
+    pub fn is_profile_enabled(&self, profile: ProfileId) -> bool {
+        self.profiles.iter().any(|(id, enabled)| *id == profile && *enabled)
+    }
+
+    /// Push next received sysex body
+    /// Returns the reply to send, if any
+    /// Messages that are not CI or not addressed to our MUID are ignored
+    pub fn advance(&mut self, body: &[u8]) -> Option<(CiHeader, CiMessage)> {
+        let (header, message) = CiMessage::decode(body).ok()?;
+        if self.invalidated || header.source == self.muid {
+            return None;
+        }
+        if let CiMessage::InvalidateMuid(muid) = message {
+            self.invalidated = muid == self.muid;
+            return None;
+        }
+        let broadcast = header.destination == BROADCAST_MUID;
+        if !broadcast && header.destination != self.muid {
+            return None;
+        }
+
+        let reply = header.reply(self.muid);
+        let message = match message {
+            CiMessage::Discovery(_) => CiMessage::DiscoveryReply(self.discovery),
+            CiMessage::ProfileInquiry => {
+                let select = |enabled: bool| self.profiles.iter().filter(|(_, e)| *e == enabled).map(|(id, _)| *id).collect();
+                CiMessage::ProfileInquiryReply { enabled: select(true), disabled: select(false) }
+            }
+            CiMessage::SetProfileOn(profile) | CiMessage::SetProfileOff(profile) => {
+                let enable = matches!(message, CiMessage::SetProfileOn(_));
+                match self.profiles.iter_mut().find(|(id, _)| *id == profile) {
+                    Some((_, enabled)) => {
+                        *enabled = enable;
+                        // state changes are reported to everyone
+                        let report = CiHeader { destination: BROADCAST_MUID, ..reply };
+                        return Some((report, if enable { CiMessage::ProfileEnabled(profile) } else { CiMessage::ProfileDisabled(profile) }));
+                    }
+                    None => CiMessage::ProfileDisabled(profile),
+                }
+            }
+            CiMessage::PeCapabilities(_) if !broadcast => match self.pe_requests {
+                Some(requests) => CiMessage::PeCapabilitiesReply(requests),
+                None => CiMessage::Nak,
+            },
+            // replies and reports need no answer
+            CiMessage::DiscoveryReply(_) | CiMessage::Nak | CiMessage::ProfileInquiryReply { .. }
+            | CiMessage::ProfileEnabled(_) | CiMessage::ProfileDisabled(_) | CiMessage::PeCapabilitiesReply(_) => return None,
+            _ if broadcast => return None,
+            _ => CiMessage::Nak,
+        };
+        Some((reply, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::U14;
+
+    const IDENTITY: DeviceIdentity = DeviceIdentity {
+        manufacturer: ManufacturerId::Extended(0x21, 0x09),
+        family: U14(0x0102),
+        model: U14(0x0003),
+        version: [1, 0, 0, 0],
+    };
+    const PROFILE: ProfileId = ProfileId([0x7E, 0x00, 0x01, 0x01, 0x00]);
+
+    fn encode(header: CiHeader, message: CiMessage) -> Vec<u8, 64> {
+        let mut body = Vec::new();
+        message.encode(&header, &mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = CiHeader::new(0x0123_4567, BROADCAST_MUID);
+        let message = CiMessage::Discovery(Discovery { identity: IDENTITY, category: 0x0C, max_sysex: 512 });
+        let body = encode(header, message.clone());
+        assert_eq!(&body[..6], &[0x7E, 0x7F, SUB_ID_CI, DISCOVERY, CI_VERSION, 0x67]);
+        assert_eq!(CiMessage::decode(&body).unwrap(), (header, message));
+
+        let mut enabled = Vec::new();
+        enabled.push(PROFILE).unwrap();
+        let reply = CiMessage::ProfileInquiryReply { enabled, disabled: Vec::new() };
+        assert_eq!(CiMessage::decode(&encode(header, reply.clone())).unwrap().1, reply);
+    }
+
+    #[test]
+    fn discovery() {
+        let mut responder = CiResponder::new(0x42, IDENTITY);
+        let body = encode(CiHeader::new(0x99, BROADCAST_MUID), CiMessage::Discovery(Discovery { identity: IDENTITY, category: 0, max_sysex: 256 }));
+        let (header, reply) = responder.advance(&body).unwrap();
+        assert_eq!((header.source, header.destination), (0x42, 0x99));
+        assert!(matches!(reply, CiMessage::DiscoveryReply(discovery) if discovery.identity == IDENTITY));
+
+        // addressed to another device
+        assert!(responder.advance(&encode(CiHeader::new(0x99, 0x43), CiMessage::ProfileInquiry)).is_none());
+
+        responder.advance(&encode(CiHeader::new(0x99, BROADCAST_MUID), CiMessage::InvalidateMuid(0x42)));
+        assert!(responder.is_invalidated());
+        assert!(responder.advance(&body).is_none());
+    }
+
+    #[test]
+    fn profiles() {
+        let mut responder = CiResponder::new(0x42, IDENTITY).with_profile(PROFILE, false);
+        let (_, reply) = responder.advance(&encode(CiHeader::new(0x99, 0x42), CiMessage::ProfileInquiry)).unwrap();
+        assert!(matches!(reply, CiMessage::ProfileInquiryReply { enabled, disabled } if enabled.is_empty() && disabled[0] == PROFILE));
+
+        let (header, report) = responder.advance(&encode(CiHeader::new(0x99, 0x42), CiMessage::SetProfileOn(PROFILE))).unwrap();
+        assert_eq!(header.destination, BROADCAST_MUID);
+        assert_eq!(report, CiMessage::ProfileEnabled(PROFILE));
+        assert!(responder.is_profile_enabled(PROFILE));
+
+        let (_, nak) = responder.advance(&encode(CiHeader::new(0x99, 0x42), CiMessage::Other(0x40))).unwrap();
+        assert_eq!(nak, CiMessage::Nak);
+    }
+}
@@ -0,0 +1,207 @@
+//! Standard controller functions of the MIDI 1.0 Control Change table
+
+use crate::{Control, MidiError, U7};
+use core::convert::TryFrom;
+use core::fmt;
+
+const LSB_OFFSET: u8 = 32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlKind {
+    /// MSB of a 14-bit controller, paired with an LSB controller 32 numbers higher
+    Msb,
+    /// LSB of a 14-bit controller
+    Lsb,
+    /// Single byte continuous controller
+    Continuous,
+    /// On/Off, values <= 63 are off and >= 64 are on
+    Switch,
+    /// RPN/NRPN selection and data increment/decrement
+    Parameter,
+    /// Channel Mode messages, see `ChannelMode`
+    ChannelMode,
+    Undefined,
+}
+
+macro_rules! control_functions {
+    ($($variant:ident = $num:literal, $name:literal, $kind:ident;)*) => {
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        pub enum ControlFunction {
+            $($variant,)*
+            /// LSB of a 14-bit controller, holds the matching MSB controller number (0-31)
+            /// Use `ControlFunction::lsb_of` to build it from an MSB controller
+            Lsb(Control),
+            Undefined(Control),
+        }
+
+        impl From<Control> for ControlFunction {
+            fn from(control: Control) -> Self {
+                match control.0 {
+                    $($num => ControlFunction::$variant,)*
+                    lsb @ 32..=63 => ControlFunction::Lsb(U7(lsb - LSB_OFFSET)),
+                    _ => ControlFunction::Undefined(control),
+                }
+            }
+        }
+
+        /// Err(InvalidControl) for an LSB whose MSB controller is not 0-31
+        impl TryFrom<ControlFunction> for Control {
+            type Error = MidiError;
+
+            fn try_from(function: ControlFunction) -> Result<Self, Self::Error> {
+                Ok(match function {
+                    $(ControlFunction::$variant => U7($num),)*
+                    ControlFunction::Lsb(msb) if msb.0 < LSB_OFFSET => U7(msb.0 + LSB_OFFSET),
+                    ControlFunction::Lsb(_) => return Err(MidiError::InvalidControl),
+                    ControlFunction::Undefined(control) => control,
+                })
+            }
+        }
+
+        impl ControlFunction {
+            pub fn kind(&self) -> ControlKind {
+                match self {
+                    $(ControlFunction::$variant => ControlKind::$kind,)*
+                    ControlFunction::Lsb(_) => ControlKind::Lsb,
+                    ControlFunction::Undefined(_) => ControlKind::Undefined,
+                }
+            }
+
+            fn name(&self) -> Option<&'static str> {
+                match self {
+                    $(ControlFunction::$variant => Some($name),)*
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+control_functions! {
+    BankSelect = 0, "Bank Select", Msb;
+    ModulationWheel = 1, "Modulation Wheel", Msb;
+    BreathController = 2, "Breath Controller", Msb;
+    FootController = 4, "Foot Controller", Msb;
+    PortamentoTime = 5, "Portamento Time", Msb;
+    DataEntry = 6, "Data Entry", Msb;
+    ChannelVolume = 7, "Channel Volume", Msb;
+    Balance = 8, "Balance", Msb;
+    Pan = 10, "Pan", Msb;
+    Expression = 11, "Expression", Msb;
+    EffectControl1 = 12, "Effect Control 1", Msb;
+    EffectControl2 = 13, "Effect Control 2", Msb;
+    GeneralPurpose1 = 16, "General Purpose 1", Msb;
+    GeneralPurpose2 = 17, "General Purpose 2", Msb;
+    GeneralPurpose3 = 18, "General Purpose 3", Msb;
+    GeneralPurpose4 = 19, "General Purpose 4", Msb;
+
+    SustainPedal = 64, "Sustain Pedal", Switch;
+    Portamento = 65, "Portamento", Switch;
+    Sostenuto = 66, "Sostenuto", Switch;
+    SoftPedal = 67, "Soft Pedal", Switch;
+    LegatoFootswitch = 68, "Legato Footswitch", Switch;
+    Hold2 = 69, "Hold 2", Switch;
+    SoundController1 = 70, "Sound Variation", Continuous;
+    SoundController2 = 71, "Timbre", Continuous;
+    SoundController3 = 72, "Release Time", Continuous;
+    SoundController4 = 73, "Attack Time", Continuous;
+    SoundController5 = 74, "Brightness", Continuous;
+    SoundController6 = 75, "Decay Time", Continuous;
+    SoundController7 = 76, "Vibrato Rate", Continuous;
+    SoundController8 = 77, "Vibrato Depth", Continuous;
+    SoundController9 = 78, "Vibrato Delay", Continuous;
+    SoundController10 = 79, "Sound Controller 10", Continuous;
+    GeneralPurpose5 = 80, "General Purpose 5", Continuous;
+    GeneralPurpose6 = 81, "General Purpose 6", Continuous;
+    GeneralPurpose7 = 82, "General Purpose 7", Continuous;
+    GeneralPurpose8 = 83, "General Purpose 8", Continuous;
+    PortamentoControl = 84, "Portamento Control", Continuous;
+    HighResolutionVelocityPrefix = 88, "High Resolution Velocity Prefix", Continuous;
+    Effects1Depth = 91, "Reverb Depth", Continuous;
+    Effects2Depth = 92, "Tremolo Depth", Continuous;
+    Effects3Depth = 93, "Chorus Depth", Continuous;
+    Effects4Depth = 94, "Detune Depth", Continuous;
+    Effects5Depth = 95, "Phaser Depth", Continuous;
+
+    DataIncrement = 96, "Data Increment", Parameter;
+    DataDecrement = 97, "Data Decrement", Parameter;
+    NrpnLsb = 98, "NRPN LSB", Parameter;
+    NrpnMsb = 99, "NRPN MSB", Parameter;
+    RpnLsb = 100, "RPN LSB", Parameter;
+    RpnMsb = 101, "RPN MSB", Parameter;
+
+    AllSoundOff = 120, "All Sound Off", ChannelMode;
+    ResetAllControllers = 121, "Reset All Controllers", ChannelMode;
+    LocalControl = 122, "Local Control", ChannelMode;
+    AllNotesOff = 123, "All Notes Off", ChannelMode;
+    OmniOff = 124, "Omni Mode Off", ChannelMode;
+    OmniOn = 125, "Omni Mode On", ChannelMode;
+    MonoOn = 126, "Mono Mode On", ChannelMode;
+    PolyOn = 127, "Poly Mode On", ChannelMode;
+}
+
+impl ControlFunction {
+    pub fn is_switch(&self) -> bool {
+        self.kind() == ControlKind::Switch
+    }
+
+    /// LSB function of MSB controller, None if controller is not 0-31
+    pub fn lsb_of(msb: Control) -> Option<Self> {
+        (msb.0 < LSB_OFFSET).then_some(ControlFunction::Lsb(msb))
+    }
+
+    /// Returns the LSB controller paired with this MSB controller
+    pub fn lsb(&self) -> Option<Control> {
+        let control = Control::try_from(*self).ok()?;
+        (control.0 < LSB_OFFSET).then(|| U7(control.0 + LSB_OFFSET))
+    }
+
+    /// Returns the MSB controller paired with this LSB controller
+    pub fn msb(&self) -> Option<Control> {
+        match self {
+            ControlFunction::Lsb(msb) => Some(*msb),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ControlFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlFunction::Lsb(msb) => write!(f, "{} LSB", ControlFunction::from(*msb)),
+            ControlFunction::Undefined(control) => write!(f, "Undefined {}", control.0),
+            function => f.write_str(function.name().unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for num in 0..=127 {
+            assert_eq!(Control::try_from(ControlFunction::from(U7(num))).unwrap(), U7(num));
+        }
+    }
+
+    #[test]
+    fn pairs() {
+        assert_eq!(ControlFunction::ModulationWheel.lsb(), Some(U7(33)));
+        assert_eq!(ControlFunction::from(U7(33)).msb(), Some(U7(1)));
+        assert_eq!(ControlFunction::SustainPedal.lsb(), None);
+        assert!(ControlFunction::from(U7(64)).is_switch());
+    }
+
+    #[test]
+    fn invalid_lsb() {
+        assert_eq!(ControlFunction::lsb_of(U7(7)), Some(ControlFunction::Lsb(U7(7))));
+        assert_eq!(ControlFunction::lsb_of(U7(32)), None);
+        assert!(matches!(Control::try_from(ControlFunction::Lsb(U7(40))), Err(MidiError::InvalidControl)));
+        assert!(matches!(Control::try_from(ControlFunction::Lsb(U7(100))), Err(MidiError::InvalidControl)));
+        assert_eq!(ControlFunction::Lsb(U7(100)).lsb(), None);
+    }
+}
@@ -0,0 +1,188 @@
+//! High resolution Control Change
+//! Controllers 0-31 send the MSB of a 14-bit value, controllers 32-63 send the matching LSB
+
+use crate::{Channel, Control, Message, MidiError, U14, U7};
+use core::convert::TryInto;
+
+const LSB_OFFSET: u8 = 32;
+
+const MSB_KNOWN: u16 = 0x8000;
+const PENDING: u16 = 0x4000;
+const VALUE: u16 = 0x3FFF;
+
+/// When to emit a value after receiving its MSB
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Control14Policy {
+    /// Hold MSB until LSB arrives
+    WaitForLsb,
+    /// Emit on MSB (with LSB cleared), then again on LSB
+    EmitOnMsb,
+    /// Hold MSB until LSB arrives or until timeout (in ms) elapsed
+    Timeout(u32),
+}
+
+/// Combines MSB and LSB Control Changes into 14-bit values, on every channel
+#[derive(Debug)]
+pub struct Control14Tracker {
+    policy: Control14Policy,
+    /// Value and flags for each channel and MSB controller
+    values: [[u16; 32]; 16],
+    /// Time each held MSB was received
+    since: [[u32; 32]; 16],
+}
+
+impl Control14Tracker {
+    pub fn new(policy: Control14Policy) -> Self {
+        Control14Tracker {
+            policy,
+            values: [[0; 32]; 16],
+            since: [[0; 32]; 16],
+        }
+    }
+
+    /// Push next message, `now` is current time in ms
+    /// Returns channel, MSB controller (0-31) and value when one is available
+    /// With Timeout policy, a held MSB superseded by a new MSB is returned before the new one is held
+    /// Controllers above 63 and messages other than Control Change are ignored
+    pub fn advance(&mut self, message: Message, now: u32) -> Option<(Channel, Control, U14)> {
+        let Message::ControlChange(channel, control, value) = message else {
+            return None;
+        };
+        let ch = (channel.0 & 0x0F) as usize;
+        match control.0 {
+            msb @ 0..=31 => {
+                let slot = &mut self.values[ch][msb as usize];
+                let superseded = match self.policy {
+                    Control14Policy::Timeout(_) if *slot & PENDING != 0 => Some((channel, control, U14(*slot & VALUE))),
+                    _ => None,
+                };
+                // new MSB resets LSB
+                *slot = MSB_KNOWN | (value.0 as u16) << 7;
+                match self.policy {
+                    Control14Policy::EmitOnMsb => return Some((channel, control, U14(*slot & VALUE))),
+                    Control14Policy::WaitForLsb => *slot |= PENDING,
+                    Control14Policy::Timeout(_) => {
+                        *slot |= PENDING;
+                        self.since[ch][msb as usize] = now;
+                    }
+                }
+                superseded
+            }
+            lsb @ 32..=63 => {
+                let msb = lsb - LSB_OFFSET;
+                let slot = &mut self.values[ch][msb as usize];
+                if *slot & MSB_KNOWN == 0 {
+                    return None;
+                }
+                *slot = MSB_KNOWN | (*slot & VALUE & !0x7F) | value.0 as u16;
+                Some((channel, U7(msb), U14(*slot & VALUE)))
+            }
+            _ => None,
+        }
+    }
+
+    /// With Timeout policy, returns a held MSB value whose LSB did not arrive in time
+    /// Call repeatedly until None is returned
+    pub fn poll(&mut self, now: u32) -> Option<(Channel, Control, U14)> {
+        let Control14Policy::Timeout(timeout) = self.policy else {
+            return None;
+        };
+        for (ch, slots) in self.values.iter_mut().enumerate() {
+            let since = &self.since[ch];
+            let expired = slots
+                .iter_mut()
+                .enumerate()
+                .find(|(msb, slot)| **slot & PENDING != 0 && now.wrapping_sub(since[*msb]) >= timeout);
+            if let Some((msb, slot)) = expired {
+                *slot &= !PENDING;
+                return Some((Channel(ch as u8), U7(msb as u8), U14(*slot & VALUE)));
+            }
+        }
+        None
+    }
+}
+
+/// Build the MSB and LSB Control Change messages of a 14-bit controller (0-31)
+pub fn control14(channel: Channel, control: impl TryInto<Control>, value: U14) -> Result<[Message; 2], MidiError> {
+    let control: Control = control.try_into().map_err(|_| MidiError::InvalidControl)?;
+    if control.0 >= LSB_OFFSET {
+        return Err(MidiError::InvalidControl);
+    }
+    let (lsb, msb) = value.into();
+    Ok([
+        Message::ControlChange(channel, control, msb),
+        Message::ControlChange(channel, U7(control.0 + LSB_OFFSET), lsb),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel;
+
+    #[test]
+    fn wait_for_lsb() {
+        let mut tracker = Control14Tracker::new(Control14Policy::WaitForLsb);
+        let [msb, lsb] = control14(channel(2), 11u8, U14(0x1FFF)).unwrap();
+        assert!(tracker.advance(msb, 0).is_none());
+        let (ch, control, value) = tracker.advance(lsb, 0).unwrap();
+        assert_eq!((ch.0, control, value), (1, U7(11), U14(0x1FFF)));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut tracker = Control14Tracker::new(Control14Policy::Timeout(10));
+        let [msb, _] = control14(channel(1), 1u8, U14(0x0280)).unwrap();
+        assert!(tracker.advance(msb, 100).is_none());
+        assert!(tracker.poll(105).is_none());
+        let (_, control, value) = tracker.poll(110).unwrap();
+        assert_eq!((control, value), (U7(1), U14(0x0280)));
+        assert!(tracker.poll(120).is_none());
+    }
+
+    #[test]
+    fn timeout_per_controller() {
+        let mut tracker = Control14Tracker::new(Control14Policy::Timeout(10));
+        let [first, _] = control14(channel(1), 1u8, U14(0x0280)).unwrap();
+        let [second, _] = control14(channel(1), 2u8, U14(0x0300)).unwrap();
+        assert!(tracker.advance(first, 100).is_none());
+        assert!(tracker.advance(second, 108).is_none());
+        let (_, control, _) = tracker.poll(110).unwrap();
+        assert_eq!(control, U7(1));
+        assert!(tracker.poll(110).is_none());
+        let (_, control, value) = tracker.poll(118).unwrap();
+        assert_eq!((control, value), (U7(2), U14(0x0300)));
+    }
+
+    #[test]
+    fn timeout_returns_superseded_msb() {
+        let mut tracker = Control14Tracker::new(Control14Policy::Timeout(10));
+        let [first, _] = control14(channel(1), 1u8, U14(0x0280)).unwrap();
+        let [second, _] = control14(channel(1), 1u8, U14(0x0300)).unwrap();
+        assert!(tracker.advance(first, 100).is_none());
+        let (_, control, value) = tracker.advance(second, 105).unwrap();
+        assert_eq!((control, value), (U7(1), U14(0x0280)));
+        assert!(tracker.poll(110).is_none());
+        let (_, _, value) = tracker.poll(115).unwrap();
+        assert_eq!(value, U14(0x0300));
+    }
+
+    #[test]
+    fn emit_on_msb() {
+        let mut tracker = Control14Tracker::new(Control14Policy::EmitOnMsb);
+        let [msb, lsb] = control14(channel(1), 7u8, U14(0x1FFF)).unwrap();
+        let (_, control, value) = tracker.advance(msb, 0).unwrap();
+        assert_eq!((control, value), (U7(7), U14(0x1F80)));
+        let (_, _, value) = tracker.advance(lsb, 0).unwrap();
+        assert_eq!(value, U14(0x1FFF));
+        let (_, _, value) = tracker.advance(msb, 0).unwrap();
+        assert_eq!(value, U14(0x1F80));
+        assert!(tracker.poll(1000).is_none());
+    }
+
+    #[test]
+    fn invalid_control() {
+        assert!(control14(channel(1), 32u8, U14(0)).is_err());
+    }
+}
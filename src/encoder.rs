@@ -0,0 +1,146 @@
+use crate::status::{is_channel_status, is_realtime};
+use crate::{Message, Packet};
+use heapless::Vec;
+
+/// Turns USB-MIDI Event Packets back into Serial (DIN) MIDI bytes
+/// Optionally uses running status to omit repeated channel status bytes
+#[derive(Debug, Default)]
+pub struct PacketEncoder {
+    running_status: bool,
+    refresh_interval: Option<u16>,
+    status: Option<u8>,
+    omitted: u16,
+}
+
+impl PacketEncoder {
+    /// Encoder that always sends the full status byte
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encoder that omits repeated channel status bytes
+    pub fn running_status() -> Self {
+        PacketEncoder {
+            running_status: true,
+            ..Self::default()
+        }
+    }
+
+    /// Send the status byte again after `interval` consecutive messages used running status
+    /// Lets receivers that missed the original status byte (e.g. late cable plug-in) resync
+    pub fn with_refresh_interval(mut self, interval: u16) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    /// Force the next channel message to be sent with its status byte
+    /// Can be called periodically from a timer for time-based refresh
+    pub fn refresh(&mut self) {
+        self.status = None;
+        self.omitted = 0;
+    }
+
+    /// Encode a packet's payload as wire bytes
+    pub fn encode(&mut self, packet: &Packet) -> Vec<u8, 3> {
+        let payload = packet.payload();
+        let mut bytes = Vec::new();
+        let Some(&first) = payload.first() else {
+            return bytes;
+        };
+
+        let mut skip = 0;
+        if is_channel_status(first) {
+            if self.running_status && self.status == Some(first) && !self.refresh_due() {
+                self.omitted = self.omitted.saturating_add(1);
+                skip = 1;
+            } else {
+                self.status = Some(first);
+                self.omitted = 0;
+            }
+        } else if !is_realtime(first) {
+            // system common and sysex (including continuation bytes) cancel running status
+            // realtime bytes may be interleaved anywhere and leave it untouched
+            self.refresh();
+        }
+
+        for byte in &payload[skip..] {
+            let _ = bytes.push(*byte);
+        }
+        bytes
+    }
+
+    /// Encode a message as wire bytes
+    pub fn encode_message(&mut self, message: Message) -> Vec<u8, 3> {
+        self.encode(&Packet::from(message))
+    }
+
+    fn refresh_due(&self) -> bool {
+        match self.refresh_interval {
+            Some(interval) => self.omitted >= interval,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, note_on, Note, U7};
+
+    #[test]
+    fn running_status_omits_repeated_status() {
+        let mut encoder = PacketEncoder::running_status();
+        let first = encoder.encode_message(note_on(channel(1), Note::C4, 100).unwrap());
+        let second = encoder.encode_message(note_on(channel(1), Note::D4, 0).unwrap());
+        assert_eq!(&first[..], &[0x90, 60, 100]);
+        assert_eq!(&second[..], &[62, 0]);
+    }
+
+    #[test]
+    fn channel_change_sends_status() {
+        let mut encoder = PacketEncoder::running_status();
+        encoder.encode_message(note_on(channel(1), Note::C4, 100).unwrap());
+        let bytes = encoder.encode_message(note_on(channel(2), Note::C4, 100).unwrap());
+        assert_eq!(&bytes[..], &[0x91, 60, 100]);
+    }
+
+    #[test]
+    fn realtime_keeps_running_status() {
+        let mut encoder = PacketEncoder::running_status();
+        encoder.encode_message(note_on(channel(1), Note::C4, 100).unwrap());
+        assert_eq!(&encoder.encode_message(Message::TimingClock)[..], &[0xF8]);
+        let bytes = encoder.encode_message(note_on(channel(1), Note::C4, 0).unwrap());
+        assert_eq!(&bytes[..], &[60, 0]);
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        let mut encoder = PacketEncoder::running_status();
+        encoder.encode_message(note_on(channel(1), Note::C4, 100).unwrap());
+        encoder.encode_message(Message::SongSelect(U7(3)));
+        let bytes = encoder.encode_message(note_on(channel(1), Note::C4, 0).unwrap());
+        assert_eq!(&bytes[..], &[0x90, 60, 0]);
+
+        encoder.encode_message(Message::MeasureEnd(U7(1)));
+        let bytes = encoder.encode_message(note_on(channel(1), Note::C4, 0).unwrap());
+        assert_eq!(&bytes[..], &[0x90, 60, 0]);
+    }
+
+    #[test]
+    fn refresh_interval_resends_status() {
+        let mut encoder = PacketEncoder::running_status().with_refresh_interval(2);
+        let msg = note_on(channel(1), Note::C4, 100).unwrap();
+        let lens: [usize; 5] = core::array::from_fn(|_| encoder.encode_message(msg).len());
+        assert_eq!(lens, [3, 2, 2, 3, 2]);
+    }
+
+    #[test]
+    fn long_running_status() {
+        let mut encoder = PacketEncoder::running_status();
+        let msg = note_on(channel(1), Note::C4, 100).unwrap();
+        assert_eq!(encoder.encode_message(msg).len(), 3);
+        for _ in 0..=u16::MAX as u32 + 1 {
+            assert_eq!(encoder.encode_message(msg).len(), 2);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Device Inquiry, from Universal Non-Realtime General Information
+//! Editors and librarians send an Identity Request to find out which devices are connected
+
+use crate::universal::{DeviceIdentity, ManufacturerId, UniversalHeader, ALL_CALL};
+use crate::{transmit_sysex, MidiError, Packet, Receive, SysexAssembler, Transmit, U7};
+use heapless::Vec;
+
+const GENERAL_INFORMATION: u8 = 0x06;
+const IDENTITY_REQUEST: u8 = 0x01;
+const IDENTITY_REPLY: u8 = 0x02;
+
+/// Largest Identity Reply body, with 3-byte manufacturer id
+pub const MAX_REPLY_LEN: usize = 15;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceInquiry {
+    IdentityRequest,
+    IdentityReply(DeviceIdentity),
+}
+
+impl DeviceInquiry {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END, returns device id and message
+    /// Returns Err(InvalidSysex) if body is not a Device Inquiry message
+    pub fn decode(body: &[u8]) -> Result<(U7, DeviceInquiry), MidiError> {
+        let (header, data) = UniversalHeader::read(body)?;
+        if header.realtime || header.sub_id1 != GENERAL_INFORMATION {
+            return Err(MidiError::InvalidSysex);
+        }
+        let inquiry = match header.sub_id2 {
+            IDENTITY_REQUEST => DeviceInquiry::IdentityRequest,
+            IDENTITY_REPLY => {
+                let (manufacturer, data) = ManufacturerId::read(data)?;
+                DeviceInquiry::IdentityReply(DeviceIdentity::read_details(manufacturer, data)?.0)
+            }
+            _ => return Err(MidiError::InvalidSysex),
+        };
+        Ok((header.device_id, inquiry))
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    /// Requests are usually sent to ALL_CALL, replies carry the id of the replying device
+    pub fn encode<const N: usize>(&self, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        match self {
+            DeviceInquiry::IdentityRequest => UniversalHeader::non_realtime(device_id, GENERAL_INFORMATION, IDENTITY_REQUEST).write(buf),
+            DeviceInquiry::IdentityReply(identity) => {
+                UniversalHeader::non_realtime(device_id, GENERAL_INFORMATION, IDENTITY_REPLY).write(buf)?;
+                identity.manufacturer.write(buf)?;
+                identity.write_details(buf)
+            }
+        }
+    }
+}
+
+/// Wraps a port to answer Identity Requests addressed to our device id
+/// All received packets are still passed through to the application
+pub struct IdentityResponder<P> {
+    port: P,
+    device_id: U7,
+    identity: DeviceIdentity,
+    /// Identity Requests are only 4 bytes long, anything longer is ignored
+    assembler: SysexAssembler<4>,
+    /// Replies that could not be transmitted, saturating
+    failed_replies: u32,
+}
+
+impl<P: Receive + Transmit> IdentityResponder<P> {
+    pub fn new(port: P, device_id: U7, identity: DeviceIdentity) -> Self {
+        IdentityResponder { port, device_id, identity, assembler: SysexAssembler::new(), failed_replies: 0 }
+    }
+
+    /// Number of Identity Replies that could not be transmitted
+    pub fn failed_replies(&self) -> u32 {
+        self.failed_replies
+    }
+
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn reply(&mut self, cable_number: u8) -> Result<(), MidiError> {
+        let mut body: Vec<u8, MAX_REPLY_LEN> = Vec::new();
+        DeviceInquiry::IdentityReply(self.identity).encode(self.device_id, &mut body)?;
+        transmit_sysex(&mut self.port, &body, cable_number)
+    }
+}
+
+impl<P: Receive + Transmit> Receive for IdentityResponder<P> {
+    fn receive(&mut self) -> Result<Option<Packet>, MidiError> {
+        let Some(packet) = self.port.receive()? else {
+            return Ok(None);
+        };
+        // other sysex may be too long or interrupted, which is none of our business
+        if let Ok(Some(body)) = self.assembler.advance(packet) {
+            if let Ok((device_id, DeviceInquiry::IdentityRequest)) = DeviceInquiry::decode(&body) {
+                // a failed reply must not swallow the received packet
+                if (device_id == self.device_id || device_id == ALL_CALL) && self.reply(packet.cable_number()).is_err() {
+                    self.failed_replies = self.failed_replies.saturating_add(1);
+                }
+            }
+        }
+        Ok(Some(packet))
+    }
+}
+
+impl<P: Transmit> Transmit for IdentityResponder<P> {
+    fn is_tx_full(&self) -> bool {
+        self.port.is_tx_full()
+    }
+
+    fn transmit(&mut self, packet: Packet) -> Result<(), MidiError> {
+        self.port.transmit(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, Message, Note, SysexPackets, U14};
+
+    const IDENTITY: DeviceIdentity = DeviceIdentity {
+        manufacturer: ManufacturerId::Short(0x41),
+        family: U14(0x0123),
+        model: U14(0x0004),
+        version: [0, 1, 2, 3],
+    };
+
+    #[derive(Default)]
+    struct Loopback {
+        rx: Vec<Packet, 8>,
+        tx: Vec<Packet, 8>,
+        /// Fail once when sending the packet at this index
+        fail_at: Option<usize>,
+    }
+
+    impl Receive for Loopback {
+        fn receive(&mut self) -> Result<Option<Packet>, MidiError> {
+            Ok((!self.rx.is_empty()).then(|| self.rx.remove(0)))
+        }
+    }
+
+    impl Transmit for Loopback {
+        fn is_tx_full(&self) -> bool {
+            self.tx.is_full()
+        }
+
+        fn transmit(&mut self, packet: Packet) -> Result<(), MidiError> {
+            if self.fail_at == Some(self.tx.len()) {
+                self.fail_at = None;
+                return Err(MidiError::BufferFull);
+            }
+            self.tx.push(packet).map_err(|_| MidiError::BufferFull)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut body: Vec<u8, MAX_REPLY_LEN> = Vec::new();
+        let extended = DeviceIdentity { manufacturer: ManufacturerId::Extended(0x20, 0x29), ..IDENTITY };
+        DeviceInquiry::IdentityReply(extended).encode(U7(3), &mut body).unwrap();
+        assert_eq!(body.len(), MAX_REPLY_LEN);
+        assert_eq!(DeviceInquiry::decode(&body).unwrap(), (U7(3), DeviceInquiry::IdentityReply(extended)));
+    }
+
+    #[test]
+    fn responder() {
+        let mut request: Vec<u8, 4> = Vec::new();
+        DeviceInquiry::IdentityRequest.encode(ALL_CALL, &mut request).unwrap();
+        let mut port = Loopback::default();
+        port.rx.extend(SysexPackets::new(&request, 2));
+        let mut responder = IdentityResponder::new(port, U7(0x10), IDENTITY);
+
+        while responder.receive().unwrap().is_some() {}
+        let port = responder.into_inner();
+        assert!(port.tx.iter().all(|packet| packet.cable_number() == 2));
+
+        let mut assembler = SysexAssembler::<16>::new();
+        let reply = port.tx.into_iter().find_map(|packet| assembler.advance(packet).unwrap()).unwrap();
+        assert_eq!(DeviceInquiry::decode(&reply).unwrap(), (U7(0x10), DeviceInquiry::IdentityReply(IDENTITY)));
+    }
+
+    #[test]
+    fn reply_failure_passes_packets_through() {
+        let mut request: Vec<u8, 4> = Vec::new();
+        DeviceInquiry::IdentityRequest.encode(ALL_CALL, &mut request).unwrap();
+        let mut port = Loopback::default();
+        port.rx.extend(SysexPackets::new(&request, 0));
+        let sent = port.rx.len();
+        while !port.tx.is_full() {
+            port.tx.push(Packet::default()).unwrap();
+        }
+        let mut responder = IdentityResponder::new(port, U7(0x10), IDENTITY);
+
+        let mut received = 0;
+        while responder.receive().unwrap().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, sent);
+        assert_eq!(responder.failed_replies(), 1);
+    }
+
+    #[test]
+    fn aborted_reply_is_terminated() {
+        let mut request: Vec<u8, 4> = Vec::new();
+        DeviceInquiry::IdentityRequest.encode(ALL_CALL, &mut request).unwrap();
+        let mut port = Loopback { fail_at: Some(2), ..Loopback::default() };
+        port.rx.extend(SysexPackets::new(&request, 0));
+        let mut responder = IdentityResponder::new(port, U7(0x10), IDENTITY);
+        while responder.receive().unwrap().is_some() {}
+        assert_eq!(responder.failed_replies(), 1);
+
+        let note = Packet::from(Message::NoteOn(channel(1), Note::C4, U7(100)));
+        responder.transmit(note).unwrap();
+        let port = responder.into_inner();
+        assert_eq!(port.tx.len(), 4);
+        assert_eq!(port.tx[2].payload(), &[0xF7]);
+
+        let mut assembler = SysexAssembler::<16>::new();
+        for packet in &port.tx {
+            assert!(matches!(assembler.advance(*packet), Ok(Some(_)) | Ok(None)));
+        }
+        assert!(!assembler.is_receiving());
+    }
+}
@@ -8,8 +8,8 @@ use heapless::Vec;
 #[cfg(feature = "usb")]
 use usb_device::UsbError;
 
-pub use message::{Message, note_off, note_on, program_change};
-pub use note::Note;
+pub use message::{Message, ChannelMode, note_off, note_on, program_change};
+pub use note::{Note, ReferencePitch};
 pub use packet::{CableNumber, CodeIndexNumber, Packet};
 
 pub use status::Status;
@@ -17,9 +17,17 @@ pub use u14::U14;
 pub use u4::U4;
 pub use u6::U6;
 pub use u7::U7;
-pub use parser::{PacketParser};
+pub use parser::{PacketParser, MessageParser, ParserMode, ParserStats};
+pub use encoder::PacketEncoder;
+pub use sysex::{SysexAssembler, SysexPackets, transmit_sysex};
+pub use parameter::{ParameterChange, ParameterDecoder, ParameterKind};
+pub use control14::{Control14Policy, Control14Tracker, control14};
+pub use control::{ControlFunction, ControlKind};
+pub use translate::{Midi1Translator, Sysex7Assembler, Sysex7Packets, scale_down, scale_up, to_midi1};
+pub use universal::{DeviceIdentity, ManufacturerId, UniversalHeader, ALL_CALL};
 pub use status::is_channel_status;
 pub use status::is_non_status;
+pub use status::is_realtime;
 pub use ports::*;
 
 mod u4;
@@ -31,9 +39,28 @@ mod note;
 mod message;
 mod packet;
 mod parser;
+mod encoder;
+mod sysex;
+mod parameter;
+mod control14;
+mod control;
+mod translate;
+mod universal;
 mod ports;
 
-#[derive(Clone, Copy, Debug)]
+pub mod ump;
+pub mod ci;
+pub mod pe;
+pub mod smf;
+pub mod mtc;
+pub mod identity;
+pub mod mmc;
+pub mod msc;
+pub mod sds;
+pub mod mts;
+pub mod scala;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 /// MIDI channel, stored as 0-15
 pub struct Channel(pub u8);
@@ -134,14 +161,21 @@ pub trait Transmit {
 #[repr(u8)]
 pub enum MidiError {
     SysexInterrupted,
+    TruncatedMessage,
     InvalidStatus(u8),
     BadPacket(Packet),
+    BadUmp(ump::Ump),
     NoModeForParameter,
     SysexOutOfBounds,
+    InvalidSysex,
+    InvalidFile,
+    /// Line number of a malformed text file
+    ParseError(u32),
     InvalidCodeIndexNumber,
     InvalidCableNumber,
     InvalidChannel,
     InvalidProgram,
+    InvalidControl,
     InvalidNote,
     InvalidVelocity,
     InvalidInteger,
@@ -1,10 +1,9 @@
 use core::convert::{TryFrom, TryInto};
 use Message::*;
-use CodeIndexNumber::{SystemCommonLen1, SystemCommonLen2, SystemCommonLen3};
-use crate::{Channel, Note, Velocity, Pressure, Program, Control, U7, Bend, CodeIndexNumber, Packet, Status, MidiError, Cull};
-use crate::status::{SYSEX_END, is_non_status, SYSEX_START};
+use crate::{Channel, Note, Velocity, Pressure, Program, Control, U7, Bend, Packet, Status, MidiError};
+use crate::status::{SYSEX_END, is_non_status, SYSEX_START, status_byte};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[allow(unused)]
 pub enum Message {
@@ -15,6 +14,8 @@ pub enum Message {
     ChannelPressure(Channel, Pressure),
     ProgramChange(Channel, Program),
     ControlChange(Channel, Control, U7),
+    /// Control Change 120-127
+    ChannelMode(Channel, ChannelMode),
     PitchBend(Channel, Bend),
 
     // System
@@ -44,6 +45,64 @@ pub enum Message {
     SysexSingleByte(u8),
 }
 
+/// Channel Mode messages are Control Changes 120-127
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelMode {
+    AllSoundOff,
+    ResetAllControllers,
+    LocalControl(bool),
+    AllNotesOff,
+    OmniOff,
+    OmniOn,
+    /// Number of channels to use, 0 means as many channels as there are voices
+    MonoOn(U7),
+    PolyOn,
+}
+
+const ALL_SOUND_OFF: u8 = 120;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+const LOCAL_CONTROL: u8 = 122;
+const ALL_NOTES_OFF: u8 = 123;
+const OMNI_OFF: u8 = 124;
+const OMNI_ON: u8 = 125;
+const MONO_ON: u8 = 126;
+const POLY_ON: u8 = 127;
+
+impl TryFrom<(Control, U7)> for ChannelMode {
+    type Error = MidiError;
+
+    fn try_from(value: (Control, U7)) -> Result<Self, Self::Error> {
+        let (control, value) = value;
+        Ok(match control.0 {
+            ALL_SOUND_OFF => ChannelMode::AllSoundOff,
+            RESET_ALL_CONTROLLERS => ChannelMode::ResetAllControllers,
+            LOCAL_CONTROL => ChannelMode::LocalControl(value.0 != 0),
+            ALL_NOTES_OFF => ChannelMode::AllNotesOff,
+            OMNI_OFF => ChannelMode::OmniOff,
+            OMNI_ON => ChannelMode::OmniOn,
+            MONO_ON => ChannelMode::MonoOn(value),
+            POLY_ON => ChannelMode::PolyOn,
+            _ => return Err(MidiError::InvalidControl),
+        })
+    }
+}
+
+impl From<ChannelMode> for (Control, U7) {
+    fn from(mode: ChannelMode) -> Self {
+        match mode {
+            ChannelMode::AllSoundOff => (U7(ALL_SOUND_OFF), U7::MIN),
+            ChannelMode::ResetAllControllers => (U7(RESET_ALL_CONTROLLERS), U7::MIN),
+            ChannelMode::LocalControl(on) => (U7(LOCAL_CONTROL), if on { U7::MAX } else { U7::MIN }),
+            ChannelMode::AllNotesOff => (U7(ALL_NOTES_OFF), U7::MIN),
+            ChannelMode::OmniOff => (U7(OMNI_OFF), U7::MIN),
+            ChannelMode::OmniOn => (U7(OMNI_ON), U7::MIN),
+            ChannelMode::MonoOn(channels) => (U7(MONO_ON), channels),
+            ChannelMode::PolyOn => (U7(POLY_ON), U7::MIN),
+        }
+    }
+}
+
 pub fn note_on(channel: Channel, note: impl TryInto<Note>, velocity: impl TryInto<Velocity>) -> Result<Message, MidiError> {
     Ok(NoteOn(
         channel,
@@ -67,55 +126,232 @@ pub fn program_change(channel: Channel, program: impl TryInto<Program>) -> Resul
     ))
 }
 
-impl TryFrom<Packet> for Message {
+impl Message {
+    /// Write message as Serial MIDI bytes, with status byte
+    /// Returns number of bytes written, or 0 if message does not fit in buffer
+    pub fn encode_into(&self, buf: &mut [u8]) -> usize {
+        let mut bytes = [0; 3];
+        if let Some(status) = status_byte(self) {
+            bytes[0] = status;
+        }
+        let len = match *self {
+            NoteOff(_, note, vel) | NoteOn(_, note, vel) | NotePressure(_, note, vel) => {
+                bytes[1] = note as u8;
+                bytes[2] = u8::from(vel);
+                3
+            }
+            ControlChange(_, ctrl, val) => {
+                bytes[1] = u8::from(ctrl);
+                bytes[2] = u8::from(val);
+                3
+            }
+            Message::ChannelMode(_, mode) => {
+                let (ctrl, val) = mode.into();
+                bytes[1] = u8::from(ctrl);
+                bytes[2] = u8::from(val);
+                3
+            }
+            PitchBend(_, bend) => {
+                let (lsb, msb) = bend.into();
+                bytes[1] = u8::from(lsb);
+                bytes[2] = u8::from(msb);
+                3
+            }
+            SongPositionPointer(lsb, msb) => {
+                bytes[1] = u8::from(lsb);
+                bytes[2] = u8::from(msb);
+                3
+            }
+            ChannelPressure(_, val) | ProgramChange(_, val) | TimeCodeQuarterFrame(val) | SongSelect(val) | MeasureEnd(val) => {
+                bytes[1] = u8::from(val);
+                2
+            }
+            TuneRequest | TimingClock | Start | Continue | Stop | ActiveSensing | SystemReset => 1,
+
+            SysexBegin(b1, b2) => {
+                bytes = [SYSEX_START, b1, b2];
+                3
+            }
+            SysexCont(b1, b2, b3) => {
+                bytes = [b1, b2, b3];
+                3
+            }
+            SysexEnd => {
+                bytes[0] = SYSEX_END;
+                1
+            }
+            SysexEnd1(b1) => {
+                bytes = [b1, SYSEX_END, 0];
+                2
+            }
+            SysexEnd2(b1, b2) => {
+                bytes = [b1, b2, SYSEX_END];
+                3
+            }
+            SysexEmpty => {
+                bytes = [SYSEX_START, SYSEX_END, 0];
+                2
+            }
+            SysexSingleByte(b1) => {
+                bytes = [SYSEX_START, b1, SYSEX_END];
+                3
+            }
+        };
+        match buf.get_mut(..len) {
+            Some(buf) => {
+                buf.copy_from_slice(&bytes[..len]);
+                len
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Decode Serial MIDI bytes of a single message, with status byte
+/// Sysex fragments of up to 3 bytes are decoded the same way as their USB-MIDI packet counterpart
+impl TryFrom<&[u8]> for Message {
     type Error = MidiError;
 
-    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
-        match (packet.code_index_number(), packet.status(), packet.channel(), packet.payload()) {
-            (CodeIndexNumber::Sysex, _, _, payload) => {
-                if is_non_status(payload[0]) {
-                    Ok(SysexCont(payload[0], payload[1], payload[2]))
-                } else {
-                    Ok(SysexBegin(payload[1], payload[2]))
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let (status, data) = match bytes {
+            [SYSEX_START, SYSEX_END] => return Ok(SysexEmpty),
+            [SYSEX_START, b1, SYSEX_END] => return Ok(SysexSingleByte(*b1)),
+            [SYSEX_START, b1, b2] => return Ok(SysexBegin(*b1, *b2)),
+            [SYSEX_END] => return Ok(SysexEnd),
+            [b1, SYSEX_END] => return Ok(SysexEnd1(*b1)),
+            [b1, b2, SYSEX_END] => return Ok(SysexEnd2(*b1, *b2)),
+            [b1, b2, b3] if is_non_status(*b1) => return Ok(SysexCont(*b1, *b2, *b3)),
+            [status, data @ ..] => (*status, data),
+            [] => return Err(MidiError::TruncatedMessage),
+        };
+
+        let channel = Channel(status & 0x0F);
+        let status = Status::try_from(status)?;
+        if data.len() + 1 < status.expected_len() as usize {
+            return Err(MidiError::TruncatedMessage);
+        }
+        Ok(match status {
+            Status::NoteOff => NoteOff(channel, Note::try_from(data[0])?, Velocity::try_from(data[1])?),
+            Status::NoteOn => NoteOn(channel, Note::try_from(data[0])?, Velocity::try_from(data[1])?),
+            Status::NotePressure => NotePressure(channel, Note::try_from(data[0])?, Pressure::try_from(data[1])?),
+            Status::ChannelPressure => ChannelPressure(channel, Pressure::try_from(data[0])?),
+            Status::ProgramChange => ProgramChange(channel, Program::try_from(data[0])?),
+            Status::ControlChange => {
+                let (control, value) = (Control::try_from(data[0])?, U7::try_from(data[1])?);
+                match ChannelMode::try_from((control, value)) {
+                    Ok(mode) => Message::ChannelMode(channel, mode),
+                    Err(_) => ControlChange(channel, control, value),
                 }
             }
-            (SystemCommonLen1, _, _, payload) if payload[0] == SYSEX_END => Ok(SysexEnd),
-            (CodeIndexNumber::SysexEndsNext2, _, _, payload) => {
-                if payload[0] == SYSEX_START {
-                    Ok(SysexEmpty)
-                } else {
-                    Ok(SysexEnd1(payload[0]))
-                }
-            },
-            (CodeIndexNumber::SysexEndsNext3, _, _, payload) => {
-                if payload[0] == SYSEX_START {
-                    Ok(SysexSingleByte(payload[1]))
-                } else {
-                    Ok(SysexEnd2(payload[0], payload[1]))
-                }
-            },
-
-            (SystemCommonLen1, Some(Status::TimingClock), ..) => Ok(TimingClock),
-            (SystemCommonLen1, Some(Status::TuneRequest), ..) => Ok(TuneRequest),
-            (SystemCommonLen1, Some(Status::Start), ..) => Ok(Start),
-            (SystemCommonLen1, Some(Status::Continue), ..) => Ok(Continue),
-            (SystemCommonLen1, Some(Status::Stop), ..) => Ok(Stop),
-            (SystemCommonLen1, Some(Status::ActiveSensing), ..) => Ok(ActiveSensing),
-            (SystemCommonLen1, Some(Status::SystemReset), ..) => Ok(SystemReset),
-            (SystemCommonLen2, Some(Status::TimeCodeQuarterFrame), _, payload) => Ok(TimeCodeQuarterFrame(U7::cull(payload[1]))),
-            (SystemCommonLen2, Some(Status::SongSelect), _, payload) => Ok(SongSelect(U7::cull(payload[1]))),
-            (SystemCommonLen2, Some(Status::MeasureEnd), _, payload) => Ok(MeasureEnd(U7::cull(payload[1]))),
-            (SystemCommonLen3, Some(Status::SystemReset), _, payload) => Ok(SongPositionPointer(U7::cull(payload[1]), U7::cull(payload[1]))),
-
-            (_, Some(Status::NoteOff), Some(channel), payload) => Ok(NoteOff(channel, Note::try_from(payload[1])?, Velocity::try_from(payload[2])?)),
-            (_, Some(Status::NoteOn), Some(channel), payload) => Ok(NoteOn(channel, Note::try_from(payload[1])?, Velocity::try_from(payload[2])?)),
-            (_, Some(Status::NotePressure), Some(channel), payload) => Ok(NotePressure(channel, Note::try_from(payload[1])?, Pressure::try_from(payload[2])?)),
-            (_, Some(Status::ChannelPressure), Some(channel), payload) => Ok(ChannelPressure(channel, Pressure::try_from(payload[1])?)),
-            (_, Some(Status::ProgramChange), Some(channel), payload) => Ok(ProgramChange(channel, U7::try_from(payload[1])?)),
-            (_, Some(Status::ControlChange), Some(channel), payload) => Ok(ControlChange(channel, Control::try_from(payload[1])?, U7::try_from(payload[2])?)),
-            (_, Some(Status::PitchBend), Some(channel), payload) => Ok(PitchBend(channel, Bend::try_from((payload[1], payload[2]))?)),
-
-            (..) => Err(MidiError::BadPacket(packet)),
+            Status::PitchBend => PitchBend(channel, Bend::try_from((data[0], data[1]))?),
+
+            Status::TimeCodeQuarterFrame => TimeCodeQuarterFrame(U7::try_from(data[0])?),
+            Status::SongPositionPointer => SongPositionPointer(U7::try_from(data[0])?, U7::try_from(data[1])?),
+            Status::SongSelect => SongSelect(U7::try_from(data[0])?),
+            Status::TuneRequest => TuneRequest,
+
+            Status::TimingClock => TimingClock,
+            Status::MeasureEnd => MeasureEnd(U7::try_from(data[0])?),
+            Status::Start => Start,
+            Status::Continue => Continue,
+            Status::Stop => Stop,
+            Status::ActiveSensing => ActiveSensing,
+            Status::SystemReset => SystemReset,
+
+            // sysex with more than 2 bytes is split into fragments
+            Status::SysexStart => return Err(MidiError::SysexOutOfBounds),
+        })
+    }
+}
+
+impl TryFrom<Packet> for Message {
+    type Error = MidiError;
+
+    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
+        match packet.payload() {
+            [] => Err(MidiError::BadPacket(packet)),
+            payload => Message::try_from(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, U14};
+
+    fn round_trip(message: Message) -> Message {
+        Message::try_from(Packet::from(message)).unwrap()
+    }
+
+    #[test]
+    fn channel_messages() {
+        let ch = channel(3);
+        for message in [
+            NoteOff(ch, Note::C4, U7(64)),
+            NoteOn(ch, Note::Gs9, U7(127)),
+            NotePressure(ch, Note::C1m, U7(1)),
+            ChannelPressure(ch, U7(90)),
+            ProgramChange(ch, U7(42)),
+            ControlChange(ch, U7(7), U7(100)),
+            ControlChange(ch, U7(119), U7(0)),
+            PitchBend(ch, U14(0)),
+            PitchBend(ch, U14(0x2000)),
+            PitchBend(ch, U14(0x3FFF)),
+        ] {
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn channel_modes() {
+        for mode in [
+            ChannelMode::AllSoundOff,
+            ChannelMode::ResetAllControllers,
+            ChannelMode::LocalControl(false),
+            ChannelMode::LocalControl(true),
+            ChannelMode::AllNotesOff,
+            ChannelMode::OmniOff,
+            ChannelMode::OmniOn,
+            ChannelMode::MonoOn(U7(4)),
+            ChannelMode::PolyOn,
+        ] {
+            let message = Message::ChannelMode(channel(16), mode);
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn system_messages() {
+        for message in [
+            TimeCodeQuarterFrame(U7(0x35)),
+            SongPositionPointer(U7(1), U7(2)),
+            SongSelect(U7(9)),
+            TuneRequest,
+            TimingClock,
+            MeasureEnd(U7(4)),
+            Start,
+            Continue,
+            Stop,
+            ActiveSensing,
+            SystemReset,
+        ] {
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn sysex_messages() {
+        for message in [
+            SysexBegin(0x7E, 0x7F),
+            SysexCont(1, 2, 3),
+            SysexEnd,
+            SysexEnd1(4),
+            SysexEnd2(5, 6),
+            SysexEmpty,
+            SysexSingleByte(0x41),
+        ] {
+            assert_eq!(round_trip(message), message);
         }
     }
 }
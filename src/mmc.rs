@@ -0,0 +1,346 @@
+//! MIDI Machine Control
+//! Commands are Universal Realtime sysex with sub-id #1 0x06, responses use sub-id #1 0x07
+//! A single message may hold several commands or response fields, one after the other
+
+use crate::mtc::Timecode;
+use crate::universal::{push, REALTIME};
+use crate::{CableNumber, MidiError, PacketList, SysexPackets, U7};
+use heapless::Vec;
+
+const MMC_COMMAND: u8 = 0x06;
+const MMC_RESPONSE: u8 = 0x07;
+
+const STOP: u8 = 0x01;
+const PLAY: u8 = 0x02;
+const DEFERRED_PLAY: u8 = 0x03;
+const FAST_FORWARD: u8 = 0x04;
+const REWIND: u8 = 0x05;
+const RECORD_STROBE: u8 = 0x06;
+const RECORD_EXIT: u8 = 0x07;
+const RECORD_PAUSE: u8 = 0x08;
+const PAUSE: u8 = 0x09;
+const EJECT: u8 = 0x0A;
+const CHASE: u8 = 0x0B;
+const COMMAND_ERROR_RESET: u8 = 0x0C;
+const MMC_RESET: u8 = 0x0D;
+const LOCATE: u8 = 0x44;
+const VARIABLE_PLAY: u8 = 0x45;
+const SEARCH: u8 = 0x46;
+const SHUTTLE: u8 = 0x47;
+const STEP: u8 = 0x48;
+
+const LOCATE_FIELD: u8 = 0x00;
+const LOCATE_TARGET: u8 = 0x01;
+
+/// Commands and fields from 0x40 to 0x77 are followed by a byte count
+const COUNTED: core::ops::RangeInclusive<u8> = 0x40..=0x77;
+/// Standard time code response fields, hours to fractional frames
+const TIME_CODE_FIELDS: core::ops::RangeInclusive<u8> = 0x01..=0x1F;
+const TIME_CODE_LEN: usize = 5;
+/// Short time code response fields, frames and fractional frames only
+const SHORT_TIME_CODE_FIELDS: core::ops::RangeInclusive<u8> = 0x21..=0x3F;
+const SHORT_TIME_CODE_LEN: usize = 2;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MmcCommand {
+    Stop,
+    Play,
+    DeferredPlay,
+    FastForward,
+    Rewind,
+    RecordStrobe,
+    RecordExit,
+    RecordPause,
+    Pause,
+    Eject,
+    Chase,
+    CommandErrorReset,
+    MmcReset,
+    /// Locate to time code
+    Locate(Timecode),
+    /// Locate to time code held in an information field
+    LocateField(u8),
+    /// Speeds are in the 3-byte standard speed format
+    VariablePlay([u8; 3]),
+    Search([u8; 3]),
+    Shuttle([u8; 3]),
+    /// Number of steps, negative backwards
+    Step(i8),
+    /// Any other command, its data is skipped
+    Other(u8),
+}
+
+/// Split next command or response field into id, data and remaining bytes
+fn split_item(data: &[u8], response: bool) -> Result<(u8, &[u8], &[u8]), MidiError> {
+    let (&id, rest) = data.split_first().ok_or(MidiError::TruncatedMessage)?;
+    let len = if COUNTED.contains(&id) {
+        let (&count, counted) = rest.split_first().ok_or(MidiError::TruncatedMessage)?;
+        return match counted.get(..count as usize) {
+            Some(item) => Ok((id, item, &counted[count as usize..])),
+            None => Err(MidiError::TruncatedMessage),
+        };
+    } else if response && TIME_CODE_FIELDS.contains(&id) {
+        TIME_CODE_LEN
+    } else if response && SHORT_TIME_CODE_FIELDS.contains(&id) {
+        SHORT_TIME_CODE_LEN
+    } else {
+        0
+    };
+    let item = rest.get(..len).ok_or(MidiError::TruncatedMessage)?;
+    Ok((id, item, &rest[len..]))
+}
+
+/// Read the universal header of an MMC message, returns device id and remaining bytes
+fn read_header(body: &[u8], sub_id1: u8) -> Result<(U7, &[u8]), MidiError> {
+    let (kind, device_id, data) = match body {
+        [kind, device_id, sub_id, data @ ..] if *sub_id == sub_id1 => (*kind, *device_id, data),
+        _ => return Err(MidiError::InvalidSysex),
+    };
+    if kind != REALTIME {
+        return Err(MidiError::InvalidSysex);
+    }
+    Ok((U7(device_id & 0x7F), data))
+}
+
+impl MmcCommand {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END, returns device id and commands
+    /// Returns Err(InvalidSysex) if body is not an MMC command message
+    pub fn decode(body: &[u8]) -> Result<(U7, MmcCommands<'_>), MidiError> {
+        let (device_id, data) = read_header(body, MMC_COMMAND)?;
+        Ok((device_id, MmcCommands { data }))
+    }
+
+    fn from_item(id: u8, data: &[u8]) -> Result<Self, MidiError> {
+        let speed = || match data {
+            [sh, sm, sl, ..] => Ok([*sh, *sm, *sl]),
+            _ => Err(MidiError::TruncatedMessage),
+        };
+        Ok(match id {
+            STOP => MmcCommand::Stop,
+            PLAY => MmcCommand::Play,
+            DEFERRED_PLAY => MmcCommand::DeferredPlay,
+            FAST_FORWARD => MmcCommand::FastForward,
+            REWIND => MmcCommand::Rewind,
+            RECORD_STROBE => MmcCommand::RecordStrobe,
+            RECORD_EXIT => MmcCommand::RecordExit,
+            RECORD_PAUSE => MmcCommand::RecordPause,
+            PAUSE => MmcCommand::Pause,
+            EJECT => MmcCommand::Eject,
+            CHASE => MmcCommand::Chase,
+            COMMAND_ERROR_RESET => MmcCommand::CommandErrorReset,
+            MMC_RESET => MmcCommand::MmcReset,
+            LOCATE => match data {
+                [LOCATE_FIELD, field, ..] => MmcCommand::LocateField(*field),
+                [LOCATE_TARGET, target @ ..] => MmcCommand::Locate(Timecode::read_standard(target)?),
+                _ => return Err(MidiError::TruncatedMessage),
+            },
+            VARIABLE_PLAY => MmcCommand::VariablePlay(speed()?),
+            SEARCH => MmcCommand::Search(speed()?),
+            SHUTTLE => MmcCommand::Shuttle(speed()?),
+            STEP => {
+                let step = *data.first().ok_or(MidiError::TruncatedMessage)?;
+                let magnitude = (step & 0x3F) as i8;
+                MmcCommand::Step(if step & 0x40 != 0 { -magnitude } else { magnitude })
+            }
+            other => MmcCommand::Other(other),
+        })
+    }
+
+    /// Append this command to an MMC message body
+    fn write_item<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let simple = match self {
+            MmcCommand::Stop => STOP,
+            MmcCommand::Play => PLAY,
+            MmcCommand::DeferredPlay => DEFERRED_PLAY,
+            MmcCommand::FastForward => FAST_FORWARD,
+            MmcCommand::Rewind => REWIND,
+            MmcCommand::RecordStrobe => RECORD_STROBE,
+            MmcCommand::RecordExit => RECORD_EXIT,
+            MmcCommand::RecordPause => RECORD_PAUSE,
+            MmcCommand::Pause => PAUSE,
+            MmcCommand::Eject => EJECT,
+            MmcCommand::Chase => CHASE,
+            MmcCommand::CommandErrorReset => COMMAND_ERROR_RESET,
+            MmcCommand::MmcReset => MMC_RESET,
+            MmcCommand::Other(id) if !COUNTED.contains(id) => *id,
+            MmcCommand::Other(id) => return push(buf, &[*id, 0]),
+            MmcCommand::Locate(timecode) => {
+                push(buf, &[LOCATE, 6, LOCATE_TARGET])?;
+                return timecode.write_standard(buf);
+            }
+            MmcCommand::LocateField(field) => return push(buf, &[LOCATE, 2, LOCATE_FIELD, *field]),
+            MmcCommand::VariablePlay(speed) => return push(buf, &[VARIABLE_PLAY, 3, speed[0], speed[1], speed[2]]),
+            MmcCommand::Search(speed) => return push(buf, &[SEARCH, 3, speed[0], speed[1], speed[2]]),
+            MmcCommand::Shuttle(speed) => return push(buf, &[SHUTTLE, 3, speed[0], speed[1], speed[2]]),
+            MmcCommand::Step(steps) => {
+                let magnitude = steps.unsigned_abs().min(0x3F);
+                return push(buf, &[STEP, 1, if *steps < 0 { 0x40 | magnitude } else { magnitude }]);
+            }
+        };
+        push(buf, &[simple])
+    }
+
+    /// Encode commands as a single sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode_all<const N: usize>(commands: &[MmcCommand], device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        push(buf, &[REALTIME, device_id.0, MMC_COMMAND])?;
+        for command in commands {
+            command.write_item(buf)?;
+        }
+        Ok(())
+    }
+
+    /// Encode this command as a sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode<const N: usize>(&self, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        Self::encode_all(core::slice::from_ref(self), device_id, buf)
+    }
+
+    /// Encode this command as complete sysex packets
+    pub fn to_packets(&self, device_id: U7, cable_number: CableNumber) -> Result<PacketList, MidiError> {
+        let mut body: Vec<u8, 16> = Vec::new();
+        self.encode(device_id, &mut body)?;
+        Ok(SysexPackets::new(&body, cable_number).collect())
+    }
+}
+
+/// Commands of an MMC message
+#[derive(Debug, Clone)]
+pub struct MmcCommands<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for MmcCommands<'a> {
+    type Item = Result<MmcCommand, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let item = split_item(self.data, false);
+        // stop after an error
+        self.data = item.as_ref().map(|(_, _, rest)| *rest).unwrap_or_default();
+        Some(item.and_then(|(id, data, _)| MmcCommand::from_item(id, data)))
+    }
+}
+
+/// A response field
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MmcResponse<'a> {
+    /// Any standard time code field (0x01-0x1F), such as Selected Time Code (0x01)
+    TimeCode(u8, Timecode),
+    /// Any other field and its data, including short time code fields (0x21-0x3F)
+    Other(u8, &'a [u8]),
+}
+
+pub const SELECTED_TIME_CODE: u8 = 0x01;
+
+impl<'a> MmcResponse<'a> {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END, returns device id and response fields
+    /// Returns Err(InvalidSysex) if body is not an MMC response message
+    pub fn decode(body: &'a [u8]) -> Result<(U7, MmcResponses<'a>), MidiError> {
+        let (device_id, data) = read_header(body, MMC_RESPONSE)?;
+        Ok((device_id, MmcResponses { data }))
+    }
+
+    /// Encode response fields as a single sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode_all<const N: usize>(responses: &[MmcResponse], device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        push(buf, &[REALTIME, device_id.0, MMC_RESPONSE])?;
+        for response in responses {
+            match response {
+                MmcResponse::TimeCode(field, timecode) => {
+                    push(buf, &[*field])?;
+                    timecode.write_standard(buf)?;
+                }
+                MmcResponse::Other(field, data) if COUNTED.contains(field) => {
+                    push(buf, &[*field, data.len() as u8])?;
+                    push(buf, data)?;
+                }
+                MmcResponse::Other(field, data) => {
+                    push(buf, &[*field])?;
+                    push(buf, data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields of an MMC response message
+#[derive(Debug, Clone)]
+pub struct MmcResponses<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for MmcResponses<'a> {
+    type Item = Result<MmcResponse<'a>, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let item = split_item(self.data, true);
+        self.data = item.as_ref().map(|(_, _, rest)| *rest).unwrap_or_default();
+        Some(item.and_then(|(id, data, _)| {
+            if TIME_CODE_FIELDS.contains(&id) {
+                Ok(MmcResponse::TimeCode(id, Timecode::read_standard(data)?))
+            } else {
+                Ok(MmcResponse::Other(id, data))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtc::FrameRate;
+    use crate::SysexAssembler;
+
+    #[test]
+    fn commands() {
+        let locate = MmcCommand::Locate(Timecode::new(1, 2, 3, 4, FrameRate::Fps30));
+        let commands = [MmcCommand::Stop, locate, MmcCommand::Step(-3), MmcCommand::Shuttle([0x41, 0, 0]), MmcCommand::DeferredPlay];
+        let mut body: Vec<u8, 32> = Vec::new();
+        MmcCommand::encode_all(&commands, U7(0x7F), &mut body).unwrap();
+        assert_eq!(&body[..12], &[0x7F, 0x7F, 0x06, STOP, LOCATE, 6, 1, 0x61, 2, 3, 4, 0]);
+
+        let (device_id, decoded) = MmcCommand::decode(&body).unwrap();
+        assert_eq!(device_id, U7(0x7F));
+        let decoded: Vec<_, 8> = decoded.map(Result::unwrap).collect();
+        assert_eq!(decoded.as_slice(), &commands);
+    }
+
+    #[test]
+    fn packets() {
+        let mut assembler = SysexAssembler::<16>::new();
+        let packets = MmcCommand::Play.to_packets(U7(1), 3).unwrap();
+        let body = packets.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert_eq!(body.as_slice(), &[0x7F, 0x01, 0x06, PLAY]);
+        assert!(MmcResponse::decode(&body).is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let timecode = Timecode::new(0, 10, 0, 5, FrameRate::Fps25);
+        let responses = [MmcResponse::TimeCode(SELECTED_TIME_CODE, timecode), MmcResponse::Other(0x48, &[1, 2])];
+        let mut body: Vec<u8, 32> = Vec::new();
+        MmcResponse::encode_all(&responses, U7(2), &mut body).unwrap();
+        let (device_id, decoded) = MmcResponse::decode(&body).unwrap();
+        assert_eq!(device_id, U7(2));
+        let decoded: Vec<_, 4> = decoded.map(Result::unwrap).collect();
+        assert_eq!(decoded.as_slice(), &responses);
+    }
+
+    #[test]
+    fn short_time_code() {
+        // short Selected Time Code, then Selected Time Code
+        let body = [0x7F, 2, MMC_RESPONSE, 0x21, 5, 0, SELECTED_TIME_CODE, 0x60, 10, 0, 5, 0];
+        let (_, decoded) = MmcResponse::decode(&body).unwrap();
+        let decoded: Vec<_, 4> = decoded.map(Result::unwrap).collect();
+        assert_eq!(decoded.as_slice(), &[
+            MmcResponse::Other(0x21, &[5, 0]),
+            MmcResponse::TimeCode(SELECTED_TIME_CODE, Timecode::new(0, 10, 0, 5, FrameRate::Fps30)),
+        ]);
+    }
+}
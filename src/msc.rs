@@ -0,0 +1,405 @@
+//! MIDI Show Control
+//! Universal Realtime sysex with sub-id #1 0x02, sub-id #2 holds the command format
+//! Cue numbers, lists and paths are ASCII digits and dots, separated by zero bytes
+
+use crate::mtc::Timecode;
+use crate::universal::{push, UniversalHeader, ALL_CALL};
+use crate::{MidiError, U14, U7};
+use heapless::Vec;
+
+const SUB_ID_MSC: u8 = 0x02;
+
+/// Command formats, each category has a general format all its devices respond to
+pub const LIGHTING: u8 = 0x01;
+pub const MOVING_LIGHTS: u8 = 0x02;
+pub const SOUND: u8 = 0x10;
+pub const MACHINERY: u8 = 0x20;
+pub const VIDEO: u8 = 0x30;
+pub const PROJECTION: u8 = 0x40;
+pub const PROCESS_CONTROL: u8 = 0x50;
+pub const PYRO: u8 = 0x60;
+pub const ALL_TYPES: u8 = 0x7F;
+
+/// Group device ids, 0x70 to 0x7E
+const GROUPS: core::ops::RangeInclusive<u8> = 0x70..=0x7E;
+
+const GO: u8 = 0x01;
+const STOP: u8 = 0x02;
+const RESUME: u8 = 0x03;
+const TIMED_GO: u8 = 0x04;
+const LOAD: u8 = 0x05;
+const SET: u8 = 0x06;
+const FIRE: u8 = 0x07;
+const ALL_OFF: u8 = 0x08;
+const RESTORE: u8 = 0x09;
+const RESET: u8 = 0x0A;
+const GO_OFF: u8 = 0x0B;
+const GO_JAM_CLOCK: u8 = 0x10;
+const STANDBY_PLUS: u8 = 0x11;
+const STANDBY_MINUS: u8 = 0x12;
+const SEQUENCE_PLUS: u8 = 0x13;
+const SEQUENCE_MINUS: u8 = 0x14;
+const START_CLOCK: u8 = 0x15;
+const STOP_CLOCK: u8 = 0x16;
+const ZERO_CLOCK: u8 = 0x17;
+const SET_CLOCK: u8 = 0x18;
+const MTC_CHASE_ON: u8 = 0x19;
+const MTC_CHASE_OFF: u8 = 0x1A;
+const OPEN_CUE_LIST: u8 = 0x1B;
+const CLOSE_CUE_LIST: u8 = 0x1C;
+const OPEN_CUE_PATH: u8 = 0x1D;
+const CLOSE_CUE_PATH: u8 = 0x1E;
+
+const TIME_LEN: usize = 5;
+
+/// Cue number, in a cue list, in a cue path
+/// A missing number means the next or current cue, depending on command
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cue<'a> {
+    pub number: Option<&'a str>,
+    pub list: Option<&'a str>,
+    pub path: Option<&'a str>,
+}
+
+/// Read a single ASCII field, empty fields are None
+fn read_field(field: &[u8]) -> Result<Option<&str>, MidiError> {
+    if !field.iter().all(|byte| byte.is_ascii_digit() || *byte == b'.') {
+        return Err(MidiError::InvalidSysex);
+    }
+    Ok((!field.is_empty()).then(|| core::str::from_utf8(field).unwrap_or_default()))
+}
+
+/// Read first ASCII field, up to a zero byte
+fn read_first(data: &[u8]) -> Result<Option<&str>, MidiError> {
+    read_field(data.split(|byte| *byte == 0).next().unwrap_or_default())
+}
+
+impl<'a> Cue<'a> {
+    pub fn new(number: &'a str) -> Self {
+        Cue { number: Some(number), ..Default::default() }
+    }
+
+    pub fn with_list(mut self, list: &'a str) -> Self {
+        self.list = Some(list);
+        self
+    }
+
+    pub fn with_path(mut self, path: &'a str) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    fn read(data: &'a [u8]) -> Result<Self, MidiError> {
+        let mut fields = data.split(|byte| *byte == 0);
+        let mut next = || read_field(fields.next().unwrap_or_default());
+        Ok(Cue { number: next()?, list: next()?, path: next()? })
+    }
+
+    /// Write fields up to the last present one, missing fields in between are left empty
+    fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let fields = [self.number, self.list, self.path];
+        let count = fields.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+        for (i, field) in fields[..count].iter().enumerate() {
+            if i > 0 {
+                push(buf, &[0])?;
+            }
+            push(buf, field.unwrap_or_default().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn write_field<const N: usize>(buf: &mut Vec<u8, N>, field: &Option<&str>) -> Result<(), MidiError> {
+    push(buf, field.unwrap_or_default().as_bytes())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MscCommand<'a> {
+    Go(Cue<'a>),
+    Stop(Cue<'a>),
+    Resume(Cue<'a>),
+    TimedGo(Timecode, Cue<'a>),
+    Load(Cue<'a>),
+    /// Set generic control to value, optionally at a time
+    Set { control: U14, value: U14, time: Option<Timecode> },
+    /// Fire macro
+    Fire(U7),
+    AllOff,
+    Restore,
+    Reset,
+    GoOff(Cue<'a>),
+    GoJamClock(Cue<'a>),
+    /// Commands below carry an optional cue list
+    StandbyPlus(Option<&'a str>),
+    StandbyMinus(Option<&'a str>),
+    SequencePlus(Option<&'a str>),
+    SequenceMinus(Option<&'a str>),
+    StartClock(Option<&'a str>),
+    StopClock(Option<&'a str>),
+    ZeroClock(Option<&'a str>),
+    SetClock(Timecode, Option<&'a str>),
+    MtcChaseOn(Option<&'a str>),
+    MtcChaseOff(Option<&'a str>),
+    OpenCueList(Option<&'a str>),
+    CloseCueList(Option<&'a str>),
+    /// Commands below carry a cue path
+    OpenCuePath(Option<&'a str>),
+    CloseCuePath(Option<&'a str>),
+    /// Any other command and its data
+    Other(u8, &'a [u8]),
+}
+
+/// Split leading time code from data
+fn read_time(data: &[u8]) -> Result<(Timecode, &[u8]), MidiError> {
+    let time = data.get(..TIME_LEN).ok_or(MidiError::TruncatedMessage)?;
+    Ok((Timecode::read_standard(time)?, &data[TIME_LEN..]))
+}
+
+impl<'a> MscCommand<'a> {
+    fn read(command: u8, data: &'a [u8]) -> Result<Self, MidiError> {
+        Ok(match command {
+            GO => MscCommand::Go(Cue::read(data)?),
+            STOP => MscCommand::Stop(Cue::read(data)?),
+            RESUME => MscCommand::Resume(Cue::read(data)?),
+            TIMED_GO => {
+                let (time, data) = read_time(data)?;
+                MscCommand::TimedGo(time, Cue::read(data)?)
+            }
+            LOAD => MscCommand::Load(Cue::read(data)?),
+            SET => match data {
+                [c_lsb, c_msb, v_lsb, v_msb, time @ ..] => MscCommand::Set {
+                    control: U14::try_from((*c_lsb, *c_msb))?,
+                    value: U14::try_from((*v_lsb, *v_msb))?,
+                    time: if time.is_empty() { None } else { Some(read_time(time)?.0) },
+                },
+                _ => return Err(MidiError::TruncatedMessage),
+            },
+            FIRE => MscCommand::Fire(U7::try_from(*data.first().ok_or(MidiError::TruncatedMessage)?)?),
+            ALL_OFF => MscCommand::AllOff,
+            RESTORE => MscCommand::Restore,
+            RESET => MscCommand::Reset,
+            GO_OFF => MscCommand::GoOff(Cue::read(data)?),
+            GO_JAM_CLOCK => MscCommand::GoJamClock(Cue::read(data)?),
+            STANDBY_PLUS => MscCommand::StandbyPlus(read_first(data)?),
+            STANDBY_MINUS => MscCommand::StandbyMinus(read_first(data)?),
+            SEQUENCE_PLUS => MscCommand::SequencePlus(read_first(data)?),
+            SEQUENCE_MINUS => MscCommand::SequenceMinus(read_first(data)?),
+            START_CLOCK => MscCommand::StartClock(read_first(data)?),
+            STOP_CLOCK => MscCommand::StopClock(read_first(data)?),
+            ZERO_CLOCK => MscCommand::ZeroClock(read_first(data)?),
+            SET_CLOCK => {
+                let (time, data) = read_time(data)?;
+                MscCommand::SetClock(time, read_first(data)?)
+            }
+            MTC_CHASE_ON => MscCommand::MtcChaseOn(read_first(data)?),
+            MTC_CHASE_OFF => MscCommand::MtcChaseOff(read_first(data)?),
+            OPEN_CUE_LIST => MscCommand::OpenCueList(read_first(data)?),
+            CLOSE_CUE_LIST => MscCommand::CloseCueList(read_first(data)?),
+            OPEN_CUE_PATH => MscCommand::OpenCuePath(read_first(data)?),
+            CLOSE_CUE_PATH => MscCommand::CloseCuePath(read_first(data)?),
+            other => MscCommand::Other(other, data),
+        })
+    }
+
+    fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        match self {
+            MscCommand::Go(cue) => Self::write_cue(buf, GO, cue),
+            MscCommand::Stop(cue) => Self::write_cue(buf, STOP, cue),
+            MscCommand::Resume(cue) => Self::write_cue(buf, RESUME, cue),
+            MscCommand::TimedGo(time, cue) => {
+                push(buf, &[TIMED_GO])?;
+                time.write_standard(buf)?;
+                cue.write(buf)
+            }
+            MscCommand::Load(cue) => Self::write_cue(buf, LOAD, cue),
+            MscCommand::Set { control, value, time } => {
+                let (c_lsb, c_msb) = <(U7, U7)>::from(*control);
+                let (v_lsb, v_msb) = <(U7, U7)>::from(*value);
+                push(buf, &[SET, c_lsb.0, c_msb.0, v_lsb.0, v_msb.0])?;
+                match time {
+                    Some(time) => time.write_standard(buf),
+                    None => Ok(()),
+                }
+            }
+            MscCommand::Fire(macro_number) => push(buf, &[FIRE, macro_number.0]),
+            MscCommand::AllOff => push(buf, &[ALL_OFF]),
+            MscCommand::Restore => push(buf, &[RESTORE]),
+            MscCommand::Reset => push(buf, &[RESET]),
+            MscCommand::GoOff(cue) => Self::write_cue(buf, GO_OFF, cue),
+            MscCommand::GoJamClock(cue) => Self::write_cue(buf, GO_JAM_CLOCK, cue),
+            MscCommand::StandbyPlus(list) => Self::write_field(buf, STANDBY_PLUS, list),
+            MscCommand::StandbyMinus(list) => Self::write_field(buf, STANDBY_MINUS, list),
+            MscCommand::SequencePlus(list) => Self::write_field(buf, SEQUENCE_PLUS, list),
+            MscCommand::SequenceMinus(list) => Self::write_field(buf, SEQUENCE_MINUS, list),
+            MscCommand::StartClock(list) => Self::write_field(buf, START_CLOCK, list),
+            MscCommand::StopClock(list) => Self::write_field(buf, STOP_CLOCK, list),
+            MscCommand::ZeroClock(list) => Self::write_field(buf, ZERO_CLOCK, list),
+            MscCommand::SetClock(time, list) => {
+                push(buf, &[SET_CLOCK])?;
+                time.write_standard(buf)?;
+                write_field(buf, list)
+            }
+            MscCommand::MtcChaseOn(list) => Self::write_field(buf, MTC_CHASE_ON, list),
+            MscCommand::MtcChaseOff(list) => Self::write_field(buf, MTC_CHASE_OFF, list),
+            MscCommand::OpenCueList(list) => Self::write_field(buf, OPEN_CUE_LIST, list),
+            MscCommand::CloseCueList(list) => Self::write_field(buf, CLOSE_CUE_LIST, list),
+            MscCommand::OpenCuePath(path) => Self::write_field(buf, OPEN_CUE_PATH, path),
+            MscCommand::CloseCuePath(path) => Self::write_field(buf, CLOSE_CUE_PATH, path),
+            MscCommand::Other(command, data) => {
+                push(buf, &[*command])?;
+                push(buf, data)
+            }
+        }
+    }
+
+    fn write_cue<const N: usize>(buf: &mut Vec<u8, N>, command: u8, cue: &Cue) -> Result<(), MidiError> {
+        push(buf, &[command])?;
+        cue.write(buf)
+    }
+
+    fn write_field<const N: usize>(buf: &mut Vec<u8, N>, command: u8, field: &Option<&str>) -> Result<(), MidiError> {
+        push(buf, &[command])?;
+        write_field(buf, field)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MscMessage<'a> {
+    /// Target device, group (0x70-0x7E) or ALL_CALL
+    pub device_id: U7,
+    pub format: u8,
+    pub command: MscCommand<'a>,
+}
+
+impl<'a> MscMessage<'a> {
+    pub fn new(device_id: U7, format: u8, command: MscCommand<'a>) -> Self {
+        MscMessage { device_id, format, command }
+    }
+
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END
+    /// Returns Err(InvalidSysex) if body is not an MSC message
+    pub fn decode(body: &'a [u8]) -> Result<Self, MidiError> {
+        let (header, data) = UniversalHeader::read(body)?;
+        if !header.realtime || header.sub_id1 != SUB_ID_MSC {
+            return Err(MidiError::InvalidSysex);
+        }
+        let (command, data) = data.split_first().ok_or(MidiError::TruncatedMessage)?;
+        Ok(MscMessage { device_id: header.device_id, format: header.sub_id2, command: MscCommand::read(*command, data)? })
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        UniversalHeader::realtime(self.device_id, SUB_ID_MSC, self.format).write(buf)?;
+        self.command.write(buf)
+    }
+}
+
+/// Accepts MSC messages addressed to a device by id, group or ALL_CALL, and by command format
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MscFilter {
+    device_id: U7,
+    format: u8,
+    /// One bit per group, 0x70 to 0x7E
+    groups: u16,
+}
+
+/// A format's category, or None for ALL_TYPES
+fn category(format: u8) -> Option<u8> {
+    (format != ALL_TYPES).then_some(format & 0x70)
+}
+
+/// General formats are the lowest of their category, lighting starts at 0x01
+fn is_general(format: u8) -> bool {
+    format == LIGHTING || format & 0x0F == 0
+}
+
+impl MscFilter {
+    pub fn new(device_id: U7, format: u8) -> Self {
+        MscFilter { device_id, format, groups: 0 }
+    }
+
+    /// Also accept messages sent to group `group` (0x70-0x7E)
+    pub fn with_group(mut self, group: U7) -> Self {
+        if GROUPS.contains(&group.0) {
+            self.groups |= 1 << (group.0 - GROUPS.start());
+        }
+        self
+    }
+
+    pub fn accepts(&self, message: &MscMessage) -> bool {
+        let id = message.device_id;
+        let device = id == self.device_id
+            || id == ALL_CALL
+            || GROUPS.contains(&id.0) && self.groups & 1 << (id.0 - GROUPS.start()) != 0;
+        let format = message.format == self.format
+            || message.format == ALL_TYPES
+            || is_general(message.format) && category(message.format) == category(self.format);
+        device && format
+    }
+
+    /// Decode sysex body, returns message if it is MSC and accepted by this filter
+    pub fn advance<'a>(&self, body: &'a [u8]) -> Option<MscMessage<'a>> {
+        MscMessage::decode(body).ok().filter(|message| self.accepts(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtc::FrameRate;
+    use crate::{SysexAssembler, SysexPackets};
+
+    #[test]
+    fn go_cue() {
+        let message = MscMessage::new(U7(1), LIGHTING, MscCommand::Go(Cue::new("23.5").with_list("1")));
+        let mut body: Vec<u8, 32> = Vec::new();
+        message.encode(&mut body).unwrap();
+        assert_eq!(body.as_slice(), b"\x7F\x01\x02\x01\x0123.5\x001");
+
+        let mut assembler = SysexAssembler::<32>::new();
+        let body = SysexPackets::new(&body, 0).find_map(|packet| assembler.advance(packet).unwrap()).unwrap();
+        assert_eq!(MscMessage::decode(&body).unwrap(), message);
+    }
+
+    #[test]
+    fn round_trip() {
+        let time = Timecode::new(1, 0, 30, 12, FrameRate::Fps25);
+        let commands = [
+            MscCommand::Go(Cue::default()),
+            MscCommand::Stop(Cue { number: None, list: None, path: Some("2") }),
+            MscCommand::TimedGo(time, Cue::new("7")),
+            MscCommand::Set { control: U14(0x0101), value: U14(0x3FFF), time: Some(time) },
+            MscCommand::Set { control: U14(1), value: U14(2), time: None },
+            MscCommand::Fire(U7(9)),
+            MscCommand::AllOff,
+            MscCommand::StandbyPlus(Some("4")),
+            MscCommand::SetClock(time, None),
+            MscCommand::OpenCuePath(Some("1.2")),
+            MscCommand::Other(0x7E, &[1, 2, 3]),
+        ];
+        for command in commands {
+            let message = MscMessage::new(ALL_CALL, ALL_TYPES, command);
+            let mut body: Vec<u8, 32> = Vec::new();
+            message.encode(&mut body).unwrap();
+            assert_eq!(MscMessage::decode(&body).unwrap(), message);
+        }
+        assert!(matches!(MscMessage::decode(b"\x7F\x01\x02\x01\x01A"), Err(MidiError::InvalidSysex)));
+    }
+
+    #[test]
+    fn filter() {
+        let filter = MscFilter::new(U7(3), MOVING_LIGHTS).with_group(U7(0x71));
+        let accepts = |device_id, format| filter.accepts(&MscMessage::new(U7(device_id), format, MscCommand::AllOff));
+        assert!(accepts(3, MOVING_LIGHTS));
+        assert!(accepts(0x7F, ALL_TYPES));
+        assert!(accepts(0x71, LIGHTING));
+        assert!(!accepts(0x72, MOVING_LIGHTS));
+        assert!(!accepts(4, MOVING_LIGHTS));
+        assert!(!accepts(3, SOUND));
+        assert!(!MscFilter::new(U7(3), SOUND).accepts(&MscMessage::new(U7(3), 0x11, MscCommand::AllOff)));
+    }
+}
@@ -0,0 +1,540 @@
+//! MIDI Time Code
+//! A timecode is sent as eight quarter frame messages spread over two frames, or at once as a Full Frame sysex when locating
+
+use crate::universal::{push, UniversalHeader};
+use crate::{Message, MidiError, U7};
+use core::fmt;
+use heapless::Vec;
+
+const SUB_ID_MTC: u8 = 0x01;
+const FULL_FRAME: u8 = 0x01;
+
+const FRAMES_PER_10_MINUTES_DF: u32 = 17982;
+const FRAMES_PER_MINUTE_DF: u32 = 1798;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum FrameRate {
+    Fps24 = 0,
+    Fps25 = 1,
+    /// 29.97 fps drop-frame, frames 0 and 1 are skipped at the start of each minute except every tenth minute
+    Fps2997Drop = 2,
+    Fps30 = 3,
+}
+
+impl FrameRate {
+    /// Nominal number of frames in a second
+    pub fn fps(&self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+        }
+    }
+
+    pub fn is_drop_frame(&self) -> bool {
+        *self == FrameRate::Fps2997Drop
+    }
+
+    /// Exact frame rate, as numerator and denominator
+    pub fn ratio(&self) -> (u32, u32) {
+        match self {
+            FrameRate::Fps2997Drop => (30000, 1001),
+            rate => (rate.fps() as u32, 1),
+        }
+    }
+
+    /// Rate from its 2-bit MTC code
+    pub fn from_code(code: u8) -> Self {
+        match code & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps2997Drop,
+            _ => FrameRate::Fps30,
+        }
+    }
+}
+
+/// SMPTE time
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+
+impl Timecode {
+    pub fn new(hours: u8, minutes: u8, seconds: u8, frames: u8, rate: FrameRate) -> Self {
+        Timecode { hours, minutes, seconds, frames, rate }
+    }
+
+    /// Number of frames since 00:00:00:00, skipping dropped frames
+    pub fn to_frames(&self) -> u32 {
+        let fps = self.rate.fps() as u32;
+        let total_minutes = self.hours as u32 * 60 + self.minutes as u32;
+        let frames = (total_minutes * 60 + self.seconds as u32) * fps + self.frames as u32;
+        if self.rate.is_drop_frame() {
+            frames - 2 * (total_minutes - total_minutes / 10)
+        } else {
+            frames
+        }
+    }
+
+    /// Timecode of frame number, wrapping after 24 hours
+    pub fn from_frames(frames: u32, rate: FrameRate) -> Self {
+        let fps = rate.fps() as u32;
+        let mut frames = frames % Self::frames_per_day(rate);
+        if rate.is_drop_frame() {
+            let tens = frames / FRAMES_PER_10_MINUTES_DF;
+            let rest = frames % FRAMES_PER_10_MINUTES_DF;
+            frames += 18 * tens;
+            if rest >= 2 {
+                frames += 2 * ((rest - 2) / FRAMES_PER_MINUTE_DF);
+            }
+        }
+        Timecode {
+            hours: (frames / (fps * 3600)) as u8,
+            minutes: (frames / (fps * 60) % 60) as u8,
+            seconds: (frames / fps % 60) as u8,
+            frames: (frames % fps) as u8,
+            rate,
+        }
+    }
+
+    fn frames_per_day(rate: FrameRate) -> u32 {
+        Timecode::new(24, 0, 0, 0, rate).to_frames()
+    }
+
+    /// Move forward or backward by a number of frames, wrapping around midnight
+    pub fn offset(&self, frames: i32) -> Self {
+        let per_day = Self::frames_per_day(self.rate) as i64;
+        let frames = (self.to_frames() as i64 + frames as i64).rem_euclid(per_day);
+        Timecode::from_frames(frames as u32, self.rate)
+    }
+
+    /// Nibble sent in quarter frame `piece` (0-7)
+    fn quarter_frame_nibble(&self, piece: u8) -> u8 {
+        match piece & 0x07 {
+            0 => self.frames & 0x0F,
+            1 => self.frames >> 4 & 0x01,
+            2 => self.seconds & 0x0F,
+            3 => self.seconds >> 4 & 0x03,
+            4 => self.minutes & 0x0F,
+            5 => self.minutes >> 4 & 0x03,
+            6 => self.hours & 0x0F,
+            _ => self.hours >> 4 & 0x01 | (self.rate as u8) << 1,
+        }
+    }
+
+    /// Quarter frame message `piece` (0-7) of this timecode
+    pub fn quarter_frame(&self, piece: u8) -> Message {
+        Message::TimeCodeQuarterFrame(U7((piece & 0x07) << 4 | self.quarter_frame_nibble(piece)))
+    }
+
+    /// Timecode from the nibbles of all eight quarter frames
+    fn from_nibbles(nibbles: &[u8; 8]) -> Self {
+        Timecode {
+            frames: nibbles[1] << 4 | nibbles[0],
+            seconds: nibbles[3] << 4 | nibbles[2],
+            minutes: nibbles[5] << 4 | nibbles[4],
+            hours: (nibbles[7] & 0x01) << 4 | nibbles[6],
+            rate: FrameRate::from_code(nibbles[7] >> 1),
+        }
+    }
+
+    /// Decode a Full Frame universal realtime sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn from_full_frame(body: &[u8]) -> Option<Self> {
+        let (header, data) = UniversalHeader::read(body).ok()?;
+        if !header.realtime || header.sub_id1 != SUB_ID_MTC || header.sub_id2 != FULL_FRAME {
+            return None;
+        }
+        Timecode::read_standard(data).ok()
+    }
+
+    pub fn write_full_frame<const N: usize>(&self, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        UniversalHeader::realtime(device_id, SUB_ID_MTC, FULL_FRAME).write(buf)?;
+        push(buf, &self.standard_bytes())
+    }
+
+    /// Hours with rate bits, minutes, seconds and frames, as in full frame and standard time code
+    fn standard_bytes(&self) -> [u8; 4] {
+        [(self.rate as u8) << 5 | self.hours & 0x1F, self.minutes & 0x3F, self.seconds & 0x3F, self.frames & 0x1F]
+    }
+
+    /// Read MMC / MSC standard time code, a trailing subframes byte is ignored
+    pub(crate) fn read_standard(data: &[u8]) -> Result<Self, MidiError> {
+        match data {
+            [hours, minutes, seconds, frames, ..] => Ok(Timecode {
+                hours: hours & 0x1F,
+                minutes: minutes & 0x3F,
+                seconds: seconds & 0x3F,
+                frames: frames & 0x1F,
+                rate: FrameRate::from_code(hours >> 5),
+            }),
+            _ => Err(MidiError::TruncatedMessage),
+        }
+    }
+
+    /// Write 5-byte MMC / MSC standard time code, with zero subframes
+    pub(crate) fn write_standard<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        push(buf, &self.standard_bytes())?;
+        push(buf, &[0])
+    }
+}
+
+impl fmt::Display for Timecode {
+    /// Drop-frame timecodes use ';' as frames separator
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.rate.is_drop_frame() { ';' } else { ':' };
+        write!(f, "{:02}:{:02}:{:02}{}{:02}", self.hours, self.minutes, self.seconds, separator, self.frames)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Assembles quarter frames and Full Frame messages into timecodes
+#[derive(Debug)]
+pub struct MtcReceiver {
+    nibbles: [u8; 8],
+    /// Bitmap of pieces received in the current cycle
+    received: u8,
+    last_piece: Option<u8>,
+    direction: Direction,
+    timecode: Option<Timecode>,
+    /// Time of last quarter frame, in ms
+    last_time: u32,
+    timeout: u32,
+}
+
+impl Default for MtcReceiver {
+    fn default() -> Self {
+        MtcReceiver {
+            nibbles: [0; 8],
+            received: 0,
+            last_piece: None,
+            direction: Direction::Forward,
+            timecode: None,
+            last_time: 0,
+            timeout: 100,
+        }
+    }
+}
+
+impl MtcReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time in ms without quarter frames after which time code is considered stopped
+    pub fn with_timeout(mut self, timeout: u32) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Last known timecode, None until synchronized
+    pub fn timecode(&self) -> Option<Timecode> {
+        self.timecode
+    }
+
+    /// Returns true if quarter frames were received within timeout
+    pub fn is_running(&self, now: u32) -> bool {
+        self.last_piece.is_some() && now.wrapping_sub(self.last_time) <= self.timeout
+    }
+
+    /// Forget partially received quarter frames
+    fn resync(&mut self) {
+        self.received = 0;
+        self.last_piece = None;
+    }
+
+    /// Push next message, `now` is current time in ms
+    /// Returns the current timecode each time it changes
+    /// Full timecodes are available after 8 quarter frames and include the two frames transmission delay,
+    /// then the timecode is advanced by one frame every 4 quarter frames
+    pub fn advance(&mut self, message: Message, now: u32) -> Option<Timecode> {
+        let Message::TimeCodeQuarterFrame(U7(data)) = message else {
+            return None;
+        };
+        if self.last_piece.is_some() && now.wrapping_sub(self.last_time) > self.timeout {
+            // dropout
+            self.resync();
+        }
+        self.last_time = now;
+
+        let piece = data >> 4 & 0x07;
+        let direction = match self.last_piece {
+            Some(last) if piece == (last + 1) & 0x07 => Direction::Forward,
+            Some(last) if piece == (last + 7) & 0x07 => Direction::Reverse,
+            None => self.direction,
+            // skipped or repeated pieces
+            Some(_) => {
+                self.received = 0;
+                self.direction
+            }
+        };
+        if direction != self.direction {
+            // pieces received before the turning point belong to another cycle
+            self.received &= self.last_piece.map_or(0, |last| 1 << last);
+            self.direction = direction;
+        }
+        self.last_piece = Some(piece);
+        self.nibbles[piece as usize] = data & 0x0F;
+        self.received |= 1 << piece;
+
+        let (step, cycle_end, frame_end) = match self.direction {
+            Direction::Forward => (1, 7, 3),
+            Direction::Reverse => (-1, 0, 4),
+        };
+        if piece == cycle_end && self.received == 0xFF {
+            self.received = 0;
+            self.timecode = Some(Timecode::from_nibbles(&self.nibbles).offset(2 * step));
+            return self.timecode;
+        }
+        if piece == frame_end {
+            if let Some(timecode) = &mut self.timecode {
+                *timecode = timecode.offset(step);
+                return self.timecode;
+            }
+        }
+        None
+    }
+
+    /// Push a received sysex body, excluding SYSEX_START and SYSEX_END
+    /// Returns the located timecode if body is a Full Frame message
+    pub fn advance_sysex(&mut self, body: &[u8]) -> Option<Timecode> {
+        let timecode = Timecode::from_full_frame(body)?;
+        self.resync();
+        self.timecode = Some(timecode);
+        self.timecode
+    }
+}
+
+/// Generates quarter frames of a running timecode
+/// Quarter frames are either paced by `poll` with a microseconds clock, or requested with `next_quarter_frame` from a timer running at 4 times the frame rate
+#[derive(Debug)]
+pub struct MtcGenerator {
+    /// Timecode sent by the current quarter frames cycle
+    timecode: Timecode,
+    piece: u8,
+    running: bool,
+    /// Time of first quarter frame since start, in us
+    start: u32,
+    /// Quarter frames sent since start
+    sent: u64,
+}
+
+impl MtcGenerator {
+    pub fn new(rate: FrameRate) -> Self {
+        MtcGenerator {
+            timecode: Timecode::new(0, 0, 0, 0, rate),
+            piece: 0,
+            running: false,
+            start: 0,
+            sent: 0,
+        }
+    }
+
+    /// Timecode of the current quarter frames cycle
+    pub fn timecode(&self) -> Timecode {
+        self.timecode
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Jump to timecode, restarting the quarter frames cycle at `now`, in us
+    /// The generator adopts the frame rate of `timecode`
+    /// Writes the Full Frame sysex body to send to receivers, excluding SYSEX_START and SYSEX_END
+    pub fn locate<const N: usize>(&mut self, now: u32, timecode: Timecode, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        self.timecode = timecode;
+        self.piece = 0;
+        self.start = now;
+        self.sent = 0;
+        self.timecode.write_full_frame(device_id, buf)
+    }
+
+    /// Start sending quarter frames, `now` is current time in us
+    pub fn start(&mut self, now: u32) {
+        self.running = true;
+        self.start = now;
+        self.sent = 0;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Next quarter frame in sequence, the timecode advances by two frames every 8 quarter frames
+    pub fn next_quarter_frame(&mut self) -> Message {
+        let message = self.timecode.quarter_frame(self.piece);
+        self.piece += 1;
+        self.sent += 1;
+        if self.piece == 8 {
+            self.piece = 0;
+            self.timecode = self.timecode.offset(2);
+        }
+        message
+    }
+
+    /// Returns the next quarter frame if it is due, `now` is current time in us
+    /// Should be called at least once per quarter frame (about every 8ms)
+    pub fn poll(&mut self, now: u32) -> Option<Message> {
+        if !self.running {
+            return None;
+        }
+        let (num, den) = self.timecode.rate.ratio();
+        // quarter frames are sent at 4 times the frame rate
+        let offset = self.sent * 1_000_000 * den as u64 / (4 * num as u64);
+        let due = self.start.wrapping_add(offset as u32);
+        if (now.wrapping_sub(due) as i32) < 0 {
+            return None;
+        }
+        Some(self.next_quarter_frame())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_frame() {
+        let rate = FrameRate::Fps2997Drop;
+        let before = Timecode::new(0, 0, 59, 29, rate);
+        assert_eq!(before.offset(1), Timecode::new(0, 1, 0, 2, rate));
+        assert_eq!(Timecode::new(0, 9, 59, 29, rate).offset(1), Timecode::new(0, 10, 0, 0, rate));
+        assert_eq!(Timecode::new(1, 0, 0, 0, rate).to_frames(), 107892);
+        for frames in (0..200_000).step_by(997) {
+            assert_eq!(Timecode::from_frames(frames, rate).to_frames(), frames);
+        }
+        assert_eq!(Timecode::new(0, 0, 0, 0, FrameRate::Fps25).offset(-1), Timecode::new(23, 59, 59, 24, FrameRate::Fps25));
+    }
+
+    #[test]
+    fn quarter_frames() {
+        let mut receiver = MtcReceiver::new();
+        let sent = Timecode::new(1, 2, 3, 4, FrameRate::Fps25);
+        let mut received = None;
+        for (i, piece) in (0..8).enumerate() {
+            received = receiver.advance(sent.quarter_frame(piece), i as u32 * 10);
+        }
+        assert_eq!(received, Some(Timecode::new(1, 2, 3, 6, FrameRate::Fps25)));
+        assert_eq!(receiver.direction(), Direction::Forward);
+
+        // next frame after 4 more quarter frames
+        let next = sent.offset(2);
+        for piece in 0..3 {
+            assert!(receiver.advance(next.quarter_frame(piece), 80).is_none());
+        }
+        assert_eq!(receiver.advance(next.quarter_frame(3), 80), Some(Timecode::new(1, 2, 3, 7, FrameRate::Fps25)));
+
+        // dropout
+        assert!(!receiver.is_running(500));
+        assert!(receiver.advance(next.quarter_frame(4), 500).is_none());
+        assert_eq!(receiver.timecode(), Some(Timecode::new(1, 2, 3, 7, FrameRate::Fps25)));
+    }
+
+    #[test]
+    fn reverse() {
+        let mut receiver = MtcReceiver::new();
+        let sent = Timecode::new(0, 0, 10, 0, FrameRate::Fps30);
+        let mut received = None;
+        for piece in (0..8).rev() {
+            received = receiver.advance(sent.quarter_frame(piece), 0);
+        }
+        assert_eq!(receiver.direction(), Direction::Reverse);
+        assert_eq!(received, Some(Timecode::new(0, 0, 9, 28, FrameRate::Fps30)));
+    }
+
+    #[test]
+    fn reversal_mid_cycle() {
+        let mut receiver = MtcReceiver::new();
+        let sent = Timecode::new(0, 0, 10, 0, FrameRate::Fps30);
+        for piece in 0..8 {
+            receiver.advance(sent.quarter_frame(piece), 0);
+        }
+        let next = Timecode::new(0, 1, 20, 2, FrameRate::Fps30);
+        for piece in 0..3 {
+            receiver.advance(next.quarter_frame(piece), 0);
+        }
+        assert!(receiver.advance(next.quarter_frame(1), 0).is_none());
+        assert_eq!(receiver.direction(), Direction::Reverse);
+        // stale pieces of the forward cycle are not mixed into a reverse timecode
+        assert!(receiver.advance(next.quarter_frame(0), 0).is_none());
+
+        let mut received = None;
+        for piece in (0..8).rev() {
+            received = receiver.advance(next.quarter_frame(piece), 0);
+        }
+        assert_eq!(received, Some(next.offset(-2)));
+    }
+
+    #[test]
+    fn full_frame() {
+        let mut receiver = MtcReceiver::new();
+        let body = [0x7F, 0x7F, 0x01, 0x01, 0x40 | 13, 30, 15, 10];
+        assert_eq!(receiver.advance_sysex(&body), Some(Timecode::new(13, 30, 15, 10, FrameRate::Fps2997Drop)));
+        assert!(receiver.advance_sysex(&[0x7F, 0x7F, 0x06, 0x01]).is_none());
+    }
+
+    #[test]
+    fn generator() {
+        let mut generator = MtcGenerator::new(FrameRate::Fps25);
+        let mut receiver = MtcReceiver::new();
+        let mut body: Vec<u8, 8> = Vec::new();
+        generator.locate(0, Timecode::new(10, 0, 0, 0, FrameRate::Fps25), U7(0x7F), &mut body).unwrap();
+        assert_eq!(receiver.advance_sysex(&body), Some(Timecode::new(10, 0, 0, 0, FrameRate::Fps25)));
+
+        generator.start(1000);
+        let mut received = None;
+        for quarter in 0..8 {
+            let now = 1000 + quarter * 10_000;
+            assert!(generator.poll(now - 1).is_none());
+            received = receiver.advance(generator.poll(now).unwrap(), now / 1000);
+        }
+        assert_eq!(received, Some(Timecode::new(10, 0, 0, 2, FrameRate::Fps25)));
+        assert_eq!(generator.timecode(), Timecode::new(10, 0, 0, 2, FrameRate::Fps25));
+    }
+
+    #[test]
+    fn locate_while_running() {
+        let mut generator = MtcGenerator::new(FrameRate::Fps25);
+        generator.start(1000);
+        for quarter in 0..4 {
+            assert!(generator.poll(1000 + quarter * 10_000).is_some());
+        }
+
+        // pacing restarts from locate, at the new frame rate
+        let located = Timecode::new(1, 0, 0, 0, FrameRate::Fps24);
+        generator.locate(35_000, located, U7(0x7F), &mut Vec::<u8, 8>::new()).unwrap();
+        assert_eq!(generator.timecode(), located);
+        assert_eq!(generator.poll(35_000), Some(located.quarter_frame(0)));
+        assert!(generator.poll(35_001).is_none());
+        assert!(generator.poll(35_000 + 10_415).is_none());
+        assert_eq!(generator.poll(35_000 + 10_416), Some(located.quarter_frame(1)));
+    }
+
+    #[test]
+    fn drop_frame_generator() {
+        let mut generator = MtcGenerator::new(FrameRate::Fps2997Drop);
+        generator.locate(0, Timecode::new(0, 0, 59, 28, FrameRate::Fps2997Drop), U7(0), &mut Vec::<u8, 8>::new()).unwrap();
+        for _ in 0..8 {
+            generator.next_quarter_frame();
+        }
+        assert_eq!(generator.timecode(), Timecode::new(0, 1, 0, 2, FrameRate::Fps2997Drop));
+    }
+}
@@ -0,0 +1,483 @@
+//! MIDI Tuning Standard
+//! Universal sysex with sub-id #1 0x08
+//! Note tunings are a semitone (0-127) plus a fraction of 1/16384 semitone
+
+use crate::universal::{push, UniversalHeader};
+use crate::{MidiError, Note, U14, U7};
+use heapless::Vec;
+
+const SUB_ID_MTS: u8 = 0x08;
+
+const BULK_DUMP_REQUEST: u8 = 0x00;
+const BULK_DUMP: u8 = 0x01;
+const SINGLE_NOTE: u8 = 0x02;
+const SINGLE_NOTE_BANK: u8 = 0x07;
+const SCALE_OCTAVE_1: u8 = 0x08;
+const SCALE_OCTAVE_2: u8 = 0x09;
+
+pub const NAME_LEN: usize = 16;
+/// Most changes in a Single Note Tuning Change message
+pub const MAX_CHANGES: usize = 127;
+const NOTES: usize = 128;
+const TUNING_LEN: usize = 3;
+const CHANGE_LEN: usize = 4;
+
+/// Bulk dump body, header, program, name, tunings and checksum
+pub const MAX_BULK_DUMP_LEN: usize = 4 + 1 + NAME_LEN + NOTES * TUNING_LEN + 1;
+
+/// Fractions per semitone
+const FRACTIONS: i32 = 1 << 14;
+/// Highest valid tuning, 7F 7F 7F means no change
+const MAX_UNITS: i32 = 0x7F * FRACTIONS + 0x3FFE;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoteTuning {
+    pub semitone: u8,
+    pub fraction: U14,
+}
+
+impl NoteTuning {
+    /// Leaves the note's tuning unchanged
+    pub const NO_CHANGE: NoteTuning = NoteTuning { semitone: 0x7F, fraction: U14(0x3FFF) };
+
+    /// Tuning of note in 12-tone equal temperament, Gs9 is out of range and clamped
+    pub fn equal(note: Note) -> Self {
+        Self::from_units(note as i32 * FRACTIONS)
+    }
+
+    /// Tuning from cents above note 0 (C-1), clamped to the valid range
+    pub fn from_cents(cents: f32) -> Self {
+        Self::from_units((cents * FRACTIONS as f32 / 100.0 + 0.5) as i32)
+    }
+
+    /// Cents above note 0 (C-1)
+    pub fn cents(&self) -> f32 {
+        self.units() as f32 * 100.0 / FRACTIONS as f32
+    }
+
+    /// Fractions of semitone above note 0
+    fn units(&self) -> i32 {
+        self.semitone as i32 * FRACTIONS + self.fraction.0 as i32
+    }
+
+    fn from_units(units: i32) -> Self {
+        let units = units.clamp(0, MAX_UNITS);
+        NoteTuning { semitone: (units / FRACTIONS) as u8, fraction: U14((units % FRACTIONS) as u16) }
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        NoteTuning { semitone: bytes[0] & 0x7F, fraction: U14(((bytes[1] & 0x7F) as u16) << 7 | (bytes[2] & 0x7F) as u16) }
+    }
+
+    fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        push(buf, &[self.semitone & 0x7F, (self.fraction.0 >> 7) as u8 & 0x7F, self.fraction.0 as u8 & 0x7F])
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TuningChange {
+    pub note: Note,
+    pub tuning: NoteTuning,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Changes<'a> {
+    Encoded(&'a [u8]),
+    Typed(&'a [TuningChange]),
+}
+
+/// Single note tuning changes, either decoded from a message or to be encoded
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TuningChanges<'a>(Changes<'a>);
+
+impl<'a> TuningChanges<'a> {
+    pub fn len(&self) -> usize {
+        match self.0 {
+            Changes::Encoded(bytes) => bytes.len() / CHANGE_LEN,
+            Changes::Typed(changes) => changes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> From<&'a [TuningChange]> for TuningChanges<'a> {
+    fn from(changes: &'a [TuningChange]) -> Self {
+        TuningChanges(Changes::Typed(changes))
+    }
+}
+
+impl<'a> Iterator for TuningChanges<'a> {
+    type Item = TuningChange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            Changes::Encoded(bytes) => {
+                let (change, rest) = (bytes.get(..CHANGE_LEN)?, &bytes[CHANGE_LEN..]);
+                *bytes = rest;
+                // 7-bit key is always a valid note
+                let note = Note::try_from(change[0] & 0x7F).ok()?;
+                Some(TuningChange { note, tuning: NoteTuning::read(&change[1..]) })
+            }
+            Changes::Typed(changes) => {
+                let (change, rest) = changes.split_first()?;
+                *changes = rest;
+                Some(*change)
+            }
+        }
+    }
+}
+
+/// Bulk tuning dump, as received
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BulkDump<'a> {
+    pub program: U7,
+    name: &'a [u8],
+    tunings: &'a [u8],
+}
+
+impl<'a> BulkDump<'a> {
+    /// Name, trailing spaces removed
+    pub fn name(&self) -> &'a str {
+        core::str::from_utf8(self.name).unwrap_or_default().trim_end()
+    }
+
+    pub fn tuning(&self, note: Note) -> NoteTuning {
+        let offset = note as usize * TUNING_LEN;
+        match self.tunings.get(offset..offset + TUNING_LEN) {
+            Some(tuning) => NoteTuning::read(tuning),
+            None => NoteTuning::NO_CHANGE,
+        }
+    }
+
+    /// Complete tuning table, notes marked NO_CHANGE keep their equal temperament tuning
+    pub fn table(&self) -> TuningTable {
+        let mut table = TuningTable::new();
+        for (note, tuning) in table.tunings.iter_mut().zip(self.tunings.chunks_exact(TUNING_LEN)) {
+            let tuning = NoteTuning::read(tuning);
+            if tuning != NoteTuning::NO_CHANGE {
+                *note = tuning;
+            }
+        }
+        table
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MtsMessage<'a> {
+    BulkDumpRequest { program: U7 },
+    BulkDump(BulkDump<'a>),
+    /// Realtime changes without bank use the original single note message
+    SingleNote { realtime: bool, bank: Option<U7>, program: U7, changes: TuningChanges<'a> },
+    /// Offsets of each pitch class from C to B, in cents (-64 to +63)
+    /// Channels are a bitmap, bit 0 is channel 1
+    ScaleOctave1 { realtime: bool, channels: u16, offsets: [i8; 12] },
+    /// Offsets of each pitch class from C to B, in 100/8192 cents (-8192 to +8191)
+    ScaleOctave2 { realtime: bool, channels: u16, offsets: [i16; 12] },
+}
+
+fn read_channels(data: &[u8]) -> Result<(u16, &[u8]), MidiError> {
+    match data {
+        [ff, gg, hh, rest @ ..] => Ok(((*ff as u16 & 0x03) << 14 | (*gg as u16 & 0x7F) << 7 | *hh as u16 & 0x7F, rest)),
+        _ => Err(MidiError::TruncatedMessage),
+    }
+}
+
+fn write_channels<const N: usize>(buf: &mut Vec<u8, N>, channels: u16) -> Result<(), MidiError> {
+    push(buf, &[(channels >> 14) as u8 & 0x03, (channels >> 7) as u8 & 0x7F, channels as u8 & 0x7F])
+}
+
+/// XOR of all bytes, excluding SYSEX_START
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, byte| acc ^ byte) & 0x7F
+}
+
+impl<'a> MtsMessage<'a> {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END, returns device id and message
+    /// Returns Err(InvalidSysex) if body is not a tuning message or if a bulk dump checksum is wrong
+    pub fn decode(body: &'a [u8]) -> Result<(U7, MtsMessage<'a>), MidiError> {
+        let (header, data) = UniversalHeader::read(body)?;
+        if header.sub_id1 != SUB_ID_MTS {
+            return Err(MidiError::InvalidSysex);
+        }
+        let realtime = header.realtime;
+        let message = match (realtime, header.sub_id2) {
+            (false, BULK_DUMP_REQUEST) => {
+                MtsMessage::BulkDumpRequest { program: U7(data.first().ok_or(MidiError::TruncatedMessage)? & 0x7F) }
+            }
+            (false, BULK_DUMP) => {
+                let end = MAX_BULK_DUMP_LEN - 1;
+                let expected = body.get(end).ok_or(MidiError::TruncatedMessage)?;
+                if checksum(&body[..end]) != *expected {
+                    return Err(MidiError::InvalidSysex);
+                }
+                MtsMessage::BulkDump(BulkDump {
+                    program: U7(data[0] & 0x7F),
+                    name: &data[1..1 + NAME_LEN],
+                    tunings: &data[1 + NAME_LEN..end - 4],
+                })
+            }
+            (true, SINGLE_NOTE) => match data {
+                [program, count, changes @ ..] => MtsMessage::SingleNote {
+                    realtime,
+                    bank: None,
+                    program: U7(program & 0x7F),
+                    changes: Self::read_changes(*count, changes)?,
+                },
+                _ => return Err(MidiError::TruncatedMessage),
+            },
+            (_, SINGLE_NOTE_BANK) => match data {
+                [bank, program, count, changes @ ..] => MtsMessage::SingleNote {
+                    realtime,
+                    bank: Some(U7(bank & 0x7F)),
+                    program: U7(program & 0x7F),
+                    changes: Self::read_changes(*count, changes)?,
+                },
+                _ => return Err(MidiError::TruncatedMessage),
+            },
+            (_, SCALE_OCTAVE_1) => {
+                let (channels, data) = read_channels(data)?;
+                let data = data.get(..12).ok_or(MidiError::TruncatedMessage)?;
+                MtsMessage::ScaleOctave1 { realtime, channels, offsets: core::array::from_fn(|i| (data[i] & 0x7F) as i8 - 64) }
+            }
+            (_, SCALE_OCTAVE_2) => {
+                let (channels, data) = read_channels(data)?;
+                let data = data.get(..24).ok_or(MidiError::TruncatedMessage)?;
+                let offset = |i: usize| ((data[i * 2] & 0x7F) as i16) << 7 | (data[i * 2 + 1] & 0x7F) as i16;
+                MtsMessage::ScaleOctave2 { realtime, channels, offsets: core::array::from_fn(|i| offset(i) - 0x2000) }
+            }
+            _ => return Err(MidiError::InvalidSysex),
+        };
+        Ok((header.device_id, message))
+    }
+
+    fn read_changes(count: u8, changes: &'a [u8]) -> Result<TuningChanges<'a>, MidiError> {
+        let changes = changes.get(..count as usize * CHANGE_LEN).ok_or(MidiError::TruncatedMessage)?;
+        Ok(TuningChanges(Changes::Encoded(changes)))
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    /// Single Note changes are Err(InvalidSysex) if there are more than MAX_CHANGES, Err(InvalidNote) if a note is above G9
+    pub fn encode<const N: usize>(&self, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        match self {
+            MtsMessage::BulkDumpRequest { program } => {
+                UniversalHeader::non_realtime(device_id, SUB_ID_MTS, BULK_DUMP_REQUEST).write(buf)?;
+                push(buf, &[program.0])
+            }
+            MtsMessage::BulkDump(dump) => {
+                let start = buf.len();
+                UniversalHeader::non_realtime(device_id, SUB_ID_MTS, BULK_DUMP).write(buf)?;
+                push(buf, &[dump.program.0])?;
+                push(buf, dump.name)?;
+                push(buf, dump.tunings)?;
+                push(buf, &[checksum(&buf[start..])])
+            }
+            MtsMessage::SingleNote { realtime, bank, program, changes } => {
+                // count and notes are single data bytes
+                if changes.len() > MAX_CHANGES {
+                    return Err(MidiError::InvalidSysex);
+                }
+                if { *changes }.any(|change| change.note as u8 > 0x7F) {
+                    return Err(MidiError::InvalidNote);
+                }
+                let header = |sub_id2| UniversalHeader { realtime: *realtime, device_id, sub_id1: SUB_ID_MTS, sub_id2 };
+                match bank {
+                    // the original message only exists in realtime
+                    None if *realtime => {
+                        header(SINGLE_NOTE).write(buf)?;
+                        push(buf, &[program.0, changes.len() as u8])?;
+                    }
+                    _ => {
+                        header(SINGLE_NOTE_BANK).write(buf)?;
+                        push(buf, &[bank.unwrap_or(U7(0)).0, program.0, changes.len() as u8])?;
+                    }
+                }
+                for change in *changes {
+                    push(buf, &[change.note as u8])?;
+                    change.tuning.write(buf)?;
+                }
+                Ok(())
+            }
+            MtsMessage::ScaleOctave1 { realtime, channels, offsets } => {
+                UniversalHeader { realtime: *realtime, device_id, sub_id1: SUB_ID_MTS, sub_id2: SCALE_OCTAVE_1 }.write(buf)?;
+                write_channels(buf, *channels)?;
+                for offset in offsets {
+                    push(buf, &[(offset.clamp(&-64, &63) + 64) as u8])?;
+                }
+                Ok(())
+            }
+            MtsMessage::ScaleOctave2 { realtime, channels, offsets } => {
+                UniversalHeader { realtime: *realtime, device_id, sub_id1: SUB_ID_MTS, sub_id2: SCALE_OCTAVE_2 }.write(buf)?;
+                write_channels(buf, *channels)?;
+                for offset in offsets {
+                    let value = (offset.clamp(&-0x2000, &0x1FFF) + 0x2000) as u16;
+                    push(buf, &[(value >> 7) as u8 & 0x7F, value as u8 & 0x7F])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Tuning of every note, equal temperament by default
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TuningTable {
+    tunings: [NoteTuning; NOTES],
+}
+
+impl Default for TuningTable {
+    fn default() -> Self {
+        TuningTable { tunings: core::array::from_fn(|note| NoteTuning { semitone: note as u8, fraction: U14(0) }) }
+    }
+}
+
+impl TuningTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tuning(&self, note: Note) -> NoteTuning {
+        self.tunings.get(note as usize).copied().unwrap_or(NoteTuning::equal(note))
+    }
+
+    /// Pitch of note, in cents above note 0 (C-1)
+    pub fn cents(&self, note: Note) -> f32 {
+        self.tuning(note).cents()
+    }
+
+    /// Retune a note, NO_CHANGE and notes above the MIDI range are ignored
+    pub fn set(&mut self, note: Note, tuning: NoteTuning) {
+        if let Some(current) = self.tunings.get_mut(note as usize) {
+            if tuning != NoteTuning::NO_CHANGE {
+                *current = tuning;
+            }
+        }
+    }
+
+    /// Retune every note from equal temperament, by pitch class offsets in fractions of semitone
+    fn set_octave(&mut self, offsets: [i32; 12]) {
+        for (note, tuning) in self.tunings.iter_mut().enumerate() {
+            *tuning = NoteTuning::from_units(note as i32 * FRACTIONS + offsets[note % 12]);
+        }
+    }
+
+    /// Update table from a tuning message
+    /// Program, bank and channels are not checked, that is up to the caller
+    pub fn apply(&mut self, message: &MtsMessage) {
+        match message {
+            MtsMessage::BulkDumpRequest { .. } => {}
+            MtsMessage::BulkDump(dump) => *self = dump.table(),
+            MtsMessage::SingleNote { changes, .. } => {
+                for change in *changes {
+                    self.set(change.note, change.tuning);
+                }
+            }
+            MtsMessage::ScaleOctave1 { offsets, .. } => {
+                self.set_octave(offsets.map(|cents| (cents as i32 * FRACTIONS * 2 + cents.signum() as i32 * 100) / 200))
+            }
+            MtsMessage::ScaleOctave2 { offsets, .. } => self.set_octave(offsets.map(|offset| offset as i32 * 2)),
+        }
+    }
+
+    /// Encode table as a bulk tuning dump, `name` is truncated or padded with spaces to 16 characters
+    pub fn write_bulk_dump<const N: usize>(&self, device_id: U7, program: U7, name: &str, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let mut name_bytes = [b' '; NAME_LEN];
+        for (byte, char) in name_bytes.iter_mut().zip(name.bytes()) {
+            *byte = char & 0x7F;
+        }
+        let mut tunings: Vec<u8, { NOTES * TUNING_LEN }> = Vec::new();
+        for tuning in &self.tunings {
+            tuning.write(&mut tunings)?;
+        }
+        MtsMessage::BulkDump(BulkDump { program, name: &name_bytes, tunings: &tunings }).encode(device_id, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cents() {
+        assert_eq!(NoteTuning::equal(Note::A4).cents(), 6900.0);
+        assert_eq!(NoteTuning::from_cents(6950.0), NoteTuning { semitone: 69, fraction: U14(0x2000) });
+        assert_eq!(NoteTuning::from_cents(-10.0), NoteTuning { semitone: 0, fraction: U14(0) });
+        assert_eq!(NoteTuning::from_cents(20000.0), NoteTuning { semitone: 0x7F, fraction: U14(0x3FFE) });
+    }
+
+    #[test]
+    fn bulk_dump() {
+        let mut table = TuningTable::new();
+        table.set(Note::C4, NoteTuning::from_cents(6025.0));
+        table.set(Note::Gs9, NoteTuning::from_cents(0.0));
+        assert_eq!(table.tuning(Note::Gs9), NoteTuning { semitone: 0x7F, fraction: U14(0x3FFE) });
+        let mut body: Vec<u8, MAX_BULK_DUMP_LEN> = Vec::new();
+        table.write_bulk_dump(U7(0), U7(5), "Werckmeister", &mut body).unwrap();
+        assert_eq!(body.len(), MAX_BULK_DUMP_LEN);
+
+        let (_, message) = MtsMessage::decode(&body).unwrap();
+        let MtsMessage::BulkDump(dump) = message else { panic!() };
+        assert_eq!(dump.program, U7(5));
+        assert_eq!(dump.name(), "Werckmeister");
+        assert_eq!(dump.table(), table);
+
+        body[30] ^= 1;
+        assert!(matches!(MtsMessage::decode(&body), Err(MidiError::InvalidSysex)));
+    }
+
+    #[test]
+    fn single_note() {
+        // realtime single note change, A4 to 440Hz and C4 a quarter tone up
+        let body = [0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02, 0x45, 0x45, 0x00, 0x00, 0x3C, 0x3C, 0x40, 0x00];
+        let (_, message) = MtsMessage::decode(&body).unwrap();
+        let mut table = TuningTable::new();
+        table.apply(&message);
+        assert_eq!(table.cents(Note::A4), 6900.0);
+        assert_eq!(table.cents(Note::C4), 6050.0);
+
+        let changes = [TuningChange { note: Note::C4, tuning: NoteTuning { semitone: 0x3C, fraction: U14(0x1000) } }];
+        let message = MtsMessage::SingleNote { realtime: false, bank: None, program: U7(1), changes: changes.as_slice().into() };
+        let mut body: Vec<u8, 16> = Vec::new();
+        message.encode(U7(2), &mut body).unwrap();
+        assert_eq!(body.as_slice(), &[0x7E, 0x02, 0x08, 0x07, 0x00, 0x01, 0x01, 0x3C, 0x3C, 0x20, 0x00]);
+        let Ok((_, MtsMessage::SingleNote { bank: Some(U7(0)), changes: decoded, .. })) = MtsMessage::decode(&body) else { panic!() };
+        assert!(decoded.eq(changes));
+
+        let too_many = [changes[0]; MAX_CHANGES + 1];
+        let message = MtsMessage::SingleNote { realtime: true, bank: None, program: U7(1), changes: too_many.as_slice().into() };
+        body.clear();
+        assert!(matches!(message.encode(U7(2), &mut body), Err(MidiError::InvalidSysex)));
+        let above_g9 = [TuningChange { note: Note::Gs9, ..changes[0] }];
+        let message = MtsMessage::SingleNote { realtime: true, bank: None, program: U7(1), changes: above_g9.as_slice().into() };
+        assert!(matches!(message.encode(U7(2), &mut body), Err(MidiError::InvalidNote)));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn scale_octave() {
+        let mut offsets = [0; 12];
+        offsets[4] = -14;
+        let message = MtsMessage::ScaleOctave1 { realtime: true, channels: 0x8001, offsets };
+        let mut body: Vec<u8, 32> = Vec::new();
+        message.encode(U7(0x7F), &mut body).unwrap();
+        assert_eq!(&body[4..8], &[0x02, 0x00, 0x01, 0x40]);
+        let Ok((_, MtsMessage::ScaleOctave1 { channels: 0x8001, offsets: decoded, .. })) = MtsMessage::decode(&body) else { panic!() };
+        assert_eq!(decoded, offsets);
+
+        let mut table = TuningTable::new();
+        table.apply(&MtsMessage::ScaleOctave2 { realtime: false, channels: 0xFFFF, offsets: [4096; 12] });
+        assert_eq!(table.cents(Note::A4), 6950.0);
+        table.apply(&message);
+        assert_eq!(table.cents(Note::A4), 6900.0);
+        assert_eq!(table.tuning(Note::E4), NoteTuning::from_cents(6386.0));
+    }
+}
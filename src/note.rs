@@ -6,8 +6,9 @@ use crate::{Cull, MidiError};
 
 use num_enum::UnsafeFromPrimitive;
 use core::convert::TryFrom;
+use num::Float;
 
-#[derive(Debug, Copy, Clone, UnsafeFromPrimitive)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, UnsafeFromPrimitive)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum Note {
@@ -147,6 +148,9 @@ impl TryFrom<u8> for Note {
     type Error = MidiError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value > Note::Gs9 as u8 {
+            return Err(MidiError::InvalidNote);
+        }
         Ok(unsafe {Note::from_unchecked(value)})
     }
 }
@@ -270,6 +274,101 @@ impl Note {
     pub const Ab9: Note = Note::Gs9;
 }
 
+/// Frequency of a reference note, A4 at 440Hz by default
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReferencePitch {
+    pub note: Note,
+    /// Frequency in Hz
+    pub frequency: f32,
+}
+
+impl Default for ReferencePitch {
+    fn default() -> Self {
+        ReferencePitch { note: Note::A4, frequency: 440.0 }
+    }
+}
+
+impl ReferencePitch {
+    pub fn new(note: Note, frequency: f32) -> Self {
+        ReferencePitch { note, frequency }
+    }
+}
+
+impl Note {
+    /// Highest note of the MIDI range, arithmetic never goes beyond it
+    pub const MAX: Note = Note::G9;
+
+    fn from_number(number: i16) -> Option<Note> {
+        u8::try_from(number).ok().filter(|number| *number <= Note::MAX as u8).and_then(|number| Note::try_from(number).ok())
+    }
+
+    /// Octave number, C4 is middle C and C1m is in octave -1
+    pub fn octave(self) -> i8 {
+        (self as u8 / 12) as i8 - 1
+    }
+
+    /// Semitones above C, 0 to 11
+    pub fn pitch_class(self) -> u8 {
+        self as u8 % 12
+    }
+
+    /// Note from pitch class (0 to 11) and octave (-1 to 9)
+    pub fn from_pitch_class(pitch_class: u8, octave: i8) -> Result<Note, MidiError> {
+        if pitch_class > 11 {
+            return Err(MidiError::InvalidNote);
+        }
+        Note::from_number((octave as i16 + 1) * 12 + pitch_class as i16).ok_or(MidiError::InvalidNote)
+    }
+
+    /// Transpose by semitones, None if result is outside the MIDI range
+    pub fn checked_transpose(self, semitones: i8) -> Option<Note> {
+        Note::from_number(self as i16 + semitones as i16)
+    }
+
+    /// Transpose by semitones, clamped to the MIDI range
+    pub fn saturating_transpose(self, semitones: i8) -> Note {
+        let number = (self as i16 + semitones as i16).clamp(0, Note::MAX as i16);
+        Note::from_number(number).unwrap_or(Note::MAX)
+    }
+
+    /// Transpose by octaves, None if result is outside the MIDI range
+    pub fn checked_transpose_octaves(self, octaves: i8) -> Option<Note> {
+        Note::from_number(self as i16 + octaves as i16 * 12)
+    }
+
+    /// Transpose by octaves, keeping the highest or lowest octave of the pitch class that fits in the MIDI range
+    pub fn saturating_transpose_octaves(self, octaves: i8) -> Note {
+        let highest = (Note::MAX as i16 - self.pitch_class() as i16) / 12;
+        let octave = (self as i16 / 12 + octaves as i16).clamp(0, highest);
+        Note::from_number(octave * 12 + self.pitch_class() as i16).unwrap_or(Note::MAX)
+    }
+
+    /// Semitones from this note up to `other`, negative if `other` is lower
+    pub fn interval(self, other: Note) -> i16 {
+        other as i16 - self as i16
+    }
+
+    /// Frequency in Hz, in equal temperament
+    pub fn frequency(self, reference: ReferencePitch) -> f32 {
+        reference.frequency * Float::exp2(reference.note.interval(self) as f32 / 12.0)
+    }
+
+    /// Nearest note of frequency in Hz, and deviation from that note in cents (-50 to +50)
+    /// None if frequency is not positive or its nearest note is outside the MIDI range
+    pub fn from_frequency(frequency: f32, reference: ReferencePitch) -> Option<(Note, f32)> {
+        if frequency.is_nan() || frequency <= 0.0 {
+            return None;
+        }
+        let semitones = reference.note as u8 as f32 + 12.0 * Float::log2(frequency / reference.frequency);
+        let nearest = Float::round(semitones);
+        if !(0.0..=Note::MAX as u8 as f32).contains(&nearest) {
+            return None;
+        }
+        Some((Note::from_number(nearest as i16)?, (semitones - nearest) * 100.0))
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -473,4 +572,51 @@ mod tests {
             note_gs9:   (Note::Gs9,128),
             note_ab9:   (Note::Ab9,128),
     }
+
+    #[test]
+    fn octaves() {
+        assert_eq!((Note::C1m.octave(), Note::C1m.pitch_class()), (-1, 0));
+        assert_eq!((Note::Bb3.octave(), Note::Bb3.pitch_class()), (3, 10));
+        assert_eq!(Note::from_pitch_class(9, 4).unwrap(), Note::A4);
+        assert!(Note::from_pitch_class(8, 9).is_err());
+        assert!(Note::from_pitch_class(12, 0).is_err());
+    }
+
+    #[test]
+    fn transpose() {
+        assert_eq!(Note::C4.checked_transpose(7), Some(Note::G4));
+        assert_eq!(Note::C4.checked_transpose(-61), None);
+        assert_eq!(Note::E9.checked_transpose(4), None);
+        assert_eq!(Note::E9.saturating_transpose(4), Note::G9);
+        assert_eq!(Note::D0.saturating_transpose(-20), Note::C1m);
+        assert_eq!(Note::A4.checked_transpose_octaves(-2), Some(Note::A2));
+        assert_eq!(Note::A4.checked_transpose_octaves(6), None);
+        assert_eq!(Note::A4.saturating_transpose_octaves(6), Note::A8);
+        assert_eq!(Note::F4.saturating_transpose_octaves(6), Note::F9);
+        assert_eq!(Note::A4.saturating_transpose_octaves(-8), Note::A1m);
+        assert_eq!(Note::C4.interval(Note::E4), 4);
+        assert_eq!(Note::C4.interval(Note::A3), -3);
+        assert_eq!(Note::C1m.interval(Note::Gs9), 128);
+        assert_eq!(Note::Gs9.interval(Note::C1m), -128);
+    }
+
+    #[test]
+    fn frequency() {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+        let reference = ReferencePitch::default();
+        assert!(close(Note::A4.frequency(reference), 440.0));
+        assert!(close(Note::C4.frequency(reference), 261.626));
+        assert!(close(Note::A3.frequency(ReferencePitch::new(Note::A4, 432.0)), 216.0));
+        assert!(close(Note::Gs9.frequency(ReferencePitch::new(Note::C1m, 8.0)), 8.0 * Float::exp2(128.0 / 12.0)));
+        assert!(close(Note::C1m.frequency(ReferencePitch::new(Note::Gs9, 13289.75)), 13289.75 / Float::exp2(128.0 / 12.0)));
+
+        let (note, cents) = Note::from_frequency(445.0, reference).unwrap();
+        assert_eq!(note, Note::A4);
+        assert!(close(cents, 19.56));
+        let (note, cents) = Note::from_frequency(254.0, reference).unwrap();
+        assert_eq!(note, Note::B3);
+        assert!(close(cents, 48.79));
+        assert_eq!(Note::from_frequency(0.0, reference), None);
+        assert_eq!(Note::from_frequency(20000.0, reference), None);
+    }
 }
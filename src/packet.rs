@@ -3,8 +3,8 @@
 
 use crate::message::Message;
 use core::convert::{TryFrom};
-use crate::{MidiError, Channel, channel};
-use crate::status::{Status, status_byte, SYSEX_START, SYSEX_END};
+use crate::{MidiError, Channel};
+use crate::status::{Status, is_channel_status, SYSEX_START};
 use CodeIndexNumber::*;
 
 use num_enum::UnsafeFromPrimitive;
@@ -27,8 +27,7 @@ impl Packet {
     }
 
     pub fn set_cable_number(&mut self, num: CableNumber) {
-        let hinum = num << 4;
-        self.bytes[0] &= (self.bytes[0] & 0x0F) | hinum;
+        self.bytes[0] = (self.bytes[0] & 0x0F) | (num << 4);
     }
 
     pub fn code_index_number(&self) -> CodeIndexNumber {
@@ -46,10 +45,10 @@ impl Packet {
 
     pub fn channel(&self) -> Option<Channel> {
         let byte = self.bytes[1];
-        if byte < NoteOff as u8 {
-            None
+        if is_channel_status(byte) {
+            Some(Channel(byte & 0x0F))
         } else {
-            Some(channel(byte))
+            None
         }
     }
 
@@ -67,16 +66,15 @@ impl Packet {
     /// Sysex body _excludes_ SYSEX_START and SYSEX_END markers
     /// Return an empty slice if packet hold no sysex data
     pub fn sysex_body(&self) -> &[u8] {
-        match self.code_index_number() {
-            Sysex =>
-                if self.bytes[1] == SYSEX_START {
-                    &self.bytes[2..]
-                } else {
-                    &self.bytes[1..]
-                }
+        let body = match self.code_index_number() {
+            Sysex => &self.bytes[1..],
             SysexEndsNext2 => &self.bytes[1..2],
             SysexEndsNext3 => &self.bytes[1..3],
             _ => &[]
+        };
+        match body.first() {
+            Some(&SYSEX_START) => &body[1..],
+            _ => body,
         }
     }
 
@@ -89,86 +87,7 @@ impl From<Message> for Packet {
     fn from(message: Message) -> Self {
         let mut packet = [0; 4];
         packet[0] = CodeIndexNumber::from(message) as u8;
-        if let Some(byte) = status_byte(&message) {
-            packet[1] = byte;
-        }
-        match message {
-            Message::NoteOff(_ch, note, vel) => {
-                packet[2] = note as u8;
-                packet[3] = u8::from(vel);
-            }
-            Message::NoteOn(_, note, vel) => {
-                packet[2] = note as u8;
-                packet[3] = u8::from(vel);
-            }
-            Message::NotePressure(_, note, pres) => {
-                packet[2] = note as u8;
-                packet[3] = u8::from(pres);
-            }
-            Message::ChannelPressure(_, pres) => {
-                packet[2] = u8::from(pres);
-            }
-            Message::ProgramChange(_, patch) => {
-                packet[2] = u8::from(patch);
-            }
-            Message::ControlChange(_, ctrl, val) => {
-                packet[2] = u8::from(ctrl);
-                packet[3] = u8::from(val);
-            }
-            Message::PitchBend(_, bend) => {
-                let (lsb, msb) = bend.into();
-                packet[2] = u8::from(lsb);
-                packet[3] = u8::from(msb);
-            }
-            Message::TimeCodeQuarterFrame(val) => {
-                packet[2] = u8::from(val);
-            }
-            Message::SongPositionPointer(p1, p2) => {
-                packet[2] = u8::from(p1);
-                packet[3] = u8::from(p2);
-            }
-            Message::SongSelect(song) => {
-                packet[2] = u8::from(song);
-            }
-
-            // Sysex packets will probably not be generated from messages,
-            // but let's support it for completeness
-            Message::SysexBegin(b1, b2) => {
-                packet[1] = SYSEX_START;
-                packet[2] = b1;
-                packet[3] = b2;
-            }
-            Message::SysexCont(b1, b2, b3) => {
-                packet[1] = b1;
-                packet[2] = b2;
-                packet[3] = b3;
-            }
-            Message::SysexEnd => {
-                packet[1] = SYSEX_END;
-            }
-            Message::SysexEnd1(b1) => {
-                packet[1] = b1;
-                packet[2] = SYSEX_END;
-            }
-            Message::SysexEnd2(b1, b2) => {
-                packet[1] = b1;
-                packet[2] = b2;
-                packet[3] = SYSEX_END;
-            }
-
-            Message::SysexEmpty => {
-                packet[1] = SYSEX_START;
-                packet[2] = SYSEX_END;
-            }
-            Message::SysexSingleByte(b1) => {
-                packet[1] = SYSEX_START;
-                packet[2] = b1;
-                packet[3] = SYSEX_END;
-            }
-
-            // remaining messages are single byte (status only)
-            _ => {}
-        }
+        message.encode_into(&mut packet[1..]);
         Self::from_raw(packet)
     }
 }
@@ -251,6 +170,7 @@ impl From<Message> for CodeIndexNumber {
             Message::ChannelPressure(_, _) => CodeIndexNumber::ChannelPressure,
             Message::ProgramChange(_, _) => CodeIndexNumber::ProgramChange,
             Message::ControlChange(_, _, _) => CodeIndexNumber::ControlChange,
+            Message::ChannelMode(_, _) => CodeIndexNumber::ControlChange,
             Message::PitchBend(_, _) => CodeIndexNumber::PitchbendChange,
             Message::TimeCodeQuarterFrame(_) => CodeIndexNumber::SystemCommonLen2,
             Message::SongPositionPointer(_, _) => CodeIndexNumber::SystemCommonLen3,
@@ -262,7 +182,7 @@ impl From<Message> for CodeIndexNumber {
             Message::Continue => CodeIndexNumber::SystemCommonLen1,
             Message::Stop => CodeIndexNumber::SystemCommonLen1,
             Message::ActiveSensing => CodeIndexNumber::SystemCommonLen1,
-            Message::SystemReset => CodeIndexNumber::NoteOn,
+            Message::SystemReset => CodeIndexNumber::SystemCommonLen1,
 
             Message::SysexBegin(..) => CodeIndexNumber::Sysex,
             Message::SysexCont(..) => CodeIndexNumber::Sysex,
@@ -0,0 +1,207 @@
+//! Registered (RPN) and Non-Registered (NRPN) Parameter Numbers
+//! Parameters are selected and set using sequences of Control Change messages
+
+use crate::{Channel, Control, Message, U14, U7};
+use heapless::Vec;
+
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const DATA_INCREMENT: u8 = 96;
+const DATA_DECREMENT: u8 = 97;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParameterKind {
+    Rpn,
+    Nrpn,
+}
+
+/// New value of a registered or non-registered parameter
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParameterChange {
+    pub kind: ParameterKind,
+    pub param: U14,
+    pub value: U14,
+}
+
+impl ParameterChange {
+    pub const PITCH_BEND_SENSITIVITY: U14 = U14(0x0000);
+    pub const FINE_TUNING: U14 = U14(0x0001);
+    pub const COARSE_TUNING: U14 = U14(0x0002);
+    pub const TUNING_PROGRAM: U14 = U14(0x0003);
+    pub const TUNING_BANK: U14 = U14(0x0004);
+    pub const MODULATION_DEPTH_RANGE: U14 = U14(0x0005);
+    /// Deselects current RPN, subsequent data entry is ignored
+    pub const RPN_NULL: U14 = U14(0x3FFF);
+
+    pub fn rpn(param: U14, value: U14) -> Self {
+        ParameterChange { kind: ParameterKind::Rpn, param, value }
+    }
+
+    pub fn nrpn(param: U14, value: U14) -> Self {
+        ParameterChange { kind: ParameterKind::Nrpn, param, value }
+    }
+
+    /// Expand into Control Change messages: parameter MSB & LSB, then data entry MSB & LSB
+    /// If `terminate` is set, RPN null is appended so that stray data entry messages can't alter the parameter
+    pub fn encode(&self, channel: Channel, terminate: bool) -> Vec<Message, 6> {
+        let (msb_control, lsb_control) = match self.kind {
+            ParameterKind::Rpn => (RPN_MSB, RPN_LSB),
+            ParameterKind::Nrpn => (NRPN_MSB, NRPN_LSB),
+        };
+        let (param_lsb, param_msb) = self.param.into();
+        let (value_lsb, value_msb) = self.value.into();
+
+        let mut messages = Vec::new();
+        let _ = messages.push(Message::ControlChange(channel, U7(msb_control), param_msb));
+        let _ = messages.push(Message::ControlChange(channel, U7(lsb_control), param_lsb));
+        let _ = messages.push(Message::ControlChange(channel, U7(DATA_ENTRY_MSB), value_msb));
+        let _ = messages.push(Message::ControlChange(channel, U7(DATA_ENTRY_LSB), value_lsb));
+        if terminate {
+            let _ = messages.push(Message::ControlChange(channel, U7(RPN_MSB), U7::MAX));
+            let _ = messages.push(Message::ControlChange(channel, U7(RPN_LSB), U7::MAX));
+        }
+        messages
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct ChannelParameter {
+    /// None until a parameter is selected
+    kind: Option<ParameterKind>,
+    param_msb: u8,
+    param_lsb: u8,
+    value: u16,
+}
+
+impl ChannelParameter {
+    fn select(&mut self, kind: ParameterKind) {
+        if self.kind != Some(kind) {
+            self.param_msb = 0;
+            self.param_lsb = 0;
+        }
+        self.kind = Some(kind);
+        // value of newly selected parameter is unknown
+        self.value = 0;
+    }
+
+    fn param(&self) -> U14 {
+        U14::from((U7(self.param_lsb), U7(self.param_msb)))
+    }
+}
+
+/// Tracks parameter selection and data entry on each channel
+#[derive(Debug, Default)]
+pub struct ParameterDecoder {
+    channels: [ChannelParameter; 16],
+}
+
+impl ParameterDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if control is used for parameter selection or data entry
+    pub fn is_parameter_control(control: Control) -> bool {
+        matches!(control.0, DATA_ENTRY_MSB | DATA_ENTRY_LSB | DATA_INCREMENT..=RPN_MSB)
+    }
+
+    /// Forget parameter selection on all channels
+    pub fn reset(&mut self) {
+        self.channels = Default::default();
+    }
+
+    /// Push next message
+    /// Returns the new parameter value after a data entry, increment or decrement
+    /// Data entry MSB clears the value's LSB, increment and decrement step by one LSB
+    /// Messages other than parameter Control Changes are ignored
+    pub fn advance(&mut self, message: Message) -> Option<(Channel, ParameterChange)> {
+        let Message::ControlChange(channel, control, value) = message else {
+            return None;
+        };
+        let state = &mut self.channels[(channel.0 & 0x0F) as usize];
+        match control.0 {
+            RPN_MSB => {
+                state.select(ParameterKind::Rpn);
+                state.param_msb = value.0;
+            }
+            RPN_LSB => {
+                state.select(ParameterKind::Rpn);
+                state.param_lsb = value.0;
+            }
+            NRPN_MSB => {
+                state.select(ParameterKind::Nrpn);
+                state.param_msb = value.0;
+            }
+            NRPN_LSB => {
+                state.select(ParameterKind::Nrpn);
+                state.param_lsb = value.0;
+            }
+            DATA_ENTRY_MSB => state.value = (value.0 as u16) << 7,
+            DATA_ENTRY_LSB => state.value = (state.value & !0x7F) | value.0 as u16,
+            DATA_INCREMENT => state.value = (state.value + 1).min(U14::MAX.0),
+            DATA_DECREMENT => state.value = state.value.saturating_sub(1),
+            _ => return None,
+        }
+
+        if matches!(control.0, DATA_ENTRY_MSB | DATA_ENTRY_LSB | DATA_INCREMENT | DATA_DECREMENT) {
+            let kind = state.kind?;
+            let param = state.param();
+            if kind == ParameterKind::Rpn && param == ParameterChange::RPN_NULL {
+                return None;
+            }
+            return Some((channel, ParameterChange { kind, param, value: U14(state.value) }));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel;
+
+    #[test]
+    fn round_trip() {
+        let mut decoder = ParameterDecoder::new();
+        let change = ParameterChange::nrpn(U14(0x1234), U14(0x0567));
+        let mut last = None;
+        for message in change.encode(channel(3), true) {
+            if let Some(decoded) = decoder.advance(message) {
+                last = Some(decoded);
+            }
+        }
+        let (ch, decoded) = last.unwrap();
+        assert_eq!(ch.0, 2);
+        assert_eq!(decoded, change);
+
+        // RPN null deselects
+        assert!(decoder.advance(Message::ControlChange(channel(3), U7(DATA_ENTRY_MSB), U7(1))).is_none());
+    }
+
+    #[test]
+    fn increment_decrement() {
+        let mut decoder = ParameterDecoder::new();
+        let ch = channel(1);
+        decoder.advance(Message::ControlChange(ch, U7(RPN_MSB), U7(0)));
+        decoder.advance(Message::ControlChange(ch, U7(RPN_LSB), U7(0)));
+        let (_, msb) = decoder.advance(Message::ControlChange(ch, U7(DATA_ENTRY_MSB), U7(2))).unwrap();
+        assert_eq!(msb, ParameterChange::rpn(ParameterChange::PITCH_BEND_SENSITIVITY, U14(0x100)));
+        let (_, inc) = decoder.advance(Message::ControlChange(ch, U7(DATA_INCREMENT), U7(0))).unwrap();
+        assert_eq!(inc.value, U14(0x101));
+        decoder.advance(Message::ControlChange(ch, U7(DATA_DECREMENT), U7(0)));
+        let (_, dec) = decoder.advance(Message::ControlChange(ch, U7(DATA_DECREMENT), U7(0))).unwrap();
+        assert_eq!(dec.value, U14(0xFF));
+    }
+
+    #[test]
+    fn no_selection() {
+        let mut decoder = ParameterDecoder::new();
+        assert!(decoder.advance(Message::ControlChange(channel(1), U7(DATA_ENTRY_MSB), U7(2))).is_none());
+    }
+}
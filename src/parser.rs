@@ -1,15 +1,20 @@
-use crate::status::{is_non_status, is_channel_status, SYSEX_END};
-use crate::{CodeIndexNumber, Packet, Status, MidiError};
+use crate::status::{is_non_status, is_channel_status, is_realtime, SYSEX_END, SYSEX_START};
+use crate::{CodeIndexNumber, Packet, Status, MidiError, Message};
 use core::convert::TryFrom;
 
+/// Bytes of a single MIDI message, or of a sysex fragment of up to 3 bytes
 #[derive(Copy, Clone, Default, Debug)]
-struct PacketBuffer {
+struct MessageBuffer {
     expected_len: u8,
     len: u8,
-    bytes: [u8; 4],
+    bytes: [u8; 3],
 }
 
-impl PacketBuffer {
+impl MessageBuffer {
+    pub fn single(byte: u8) -> Self {
+        MessageBuffer { expected_len: 1, len: 1, bytes: [byte, 0, 0] }
+    }
+
     pub fn is_full(&self) -> bool {
         self.len >= self.expected_len
     }
@@ -18,75 +23,386 @@ impl PacketBuffer {
         self.len != 0
     }
 
-    pub fn push(&mut self, byte: u8) {
-        assert!(!self.is_full(), "MIDI Packet Length Exceeded {} >= {}", self.len, self.expected_len);
-        self.len += 1;
+    pub fn push(&mut self, byte: u8) -> Result<(), MidiError> {
+        if self.is_full() {
+            return Err(MidiError::BufferFull);
+        }
         self.bytes[self.len as usize] = byte;
+        self.len += 1;
+        Ok(())
     }
 
-    pub fn build(&mut self, cin: CodeIndexNumber) -> Packet {
-        self.bytes[0] = cin as u8;
-        let packet = Packet::from_raw(self.bytes);
+    /// Returns the buffered message, leaving the buffer empty and ready for next message
+    pub fn take(&mut self) -> MessageBuffer {
+        let message = *self;
         self.clear(self.expected_len);
-        packet
+        message
     }
 
     pub fn clear(&mut self, new_limit: u8) {
         self.len = 0;
-        self.bytes = [0; 4];
+        self.bytes = [0; 3];
         self.expected_len = new_limit;
     }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn code_index_number(&self) -> Result<CodeIndexNumber, MidiError> {
+        match self.bytes() {
+            [.., SYSEX_END] => CodeIndexNumber::end_sysex(self.len),
+            [first, ..] if *first == SYSEX_START || is_non_status(*first) => Ok(CodeIndexNumber::Sysex),
+            [status, ..] => Ok(CodeIndexNumber::from(Status::try_from(*status)?)),
+            [] => Err(MidiError::TruncatedMessage),
+        }
+    }
+}
+
+/// How the parser reacts to malformed input
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParserMode {
+    /// Silently drop malformed input, resync on next status byte
+    #[default]
+    Lenient,
+    /// Report malformed input as errors, then resync on next status byte
+    Strict,
+}
+
+/// Diagnostics counters, saturating
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParserStats {
+    /// Data bytes without a status to apply to, undefined status bytes and stray SYSEX_END
+    pub dropped_bytes: u32,
+    /// Messages cut short by a new status byte
+    pub truncated_messages: u32,
+    /// Sysex cut short by a status byte other than SYSEX_END
+    pub aborted_sysex: u32,
 }
 
 /// USB Event Packets are used to move MIDI across Serial and USB devices
 #[derive(Debug, Default)]
 pub struct PacketParser {
-    status: Option<Status>,
-    buffer: PacketBuffer,
+    parser: ByteParser,
 }
 
 impl PacketParser {
+    pub fn new(mode: ParserMode) -> Self {
+        PacketParser { parser: ByteParser::new(mode) }
+    }
+
+    /// Parser that reports malformed input as errors
+    pub fn strict() -> Self {
+        Self::new(ParserMode::Strict)
+    }
+
+    pub fn stats(&self) -> ParserStats {
+        self.parser.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.parser.stats = ParserStats::default();
+    }
+
     /// Push new payload byte
     /// returns:
     /// - Ok(None) if packet is incomplete
     /// - Ok(Some(packet)) if packet is complete - should not be pushed to anymore, waiting on either sysex or sysex_end
+    /// - Err(_) in strict mode, if input was malformed. Parser is ready for next byte.
+    ///
+    /// In strict mode, a Tune Request that interrupts a message is dropped in favor of reporting the error
     pub fn advance(&mut self, byte: u8) -> Result<Option<Packet>, MidiError> {
-        if is_non_status(byte) {
-            if let Some(status) = self.status {
-                if !self.buffer.is_started() && is_channel_status(status as u8) {
-                    // running status, repeat last
-                    self.buffer.clear(self.buffer.expected_len);
-                    self.buffer.push(status as u8);
-                }
-                self.buffer.push(byte);
-
-                if byte == SYSEX_END {
-                    self.status = None;
-                    return Ok(Some(self.buffer.build(CodeIndexNumber::end_sysex(self.buffer.len)?)));
-                }
-                if self.buffer.is_full() {
-                    return Ok(Some(self.buffer.build(CodeIndexNumber::from(status))));
-                }
+        match self.parser.advance(byte)? {
+            Some(message) => {
+                let mut bytes = [message.code_index_number()? as u8, 0, 0, 0];
+                bytes[1..].copy_from_slice(&message.bytes);
+                Ok(Some(Packet::from_raw(bytes)))
             }
-            return Ok(None);
-        }
-
-        if let Ok(status) = Status::try_from(byte) {
-            match status.expected_len() {
-                1 => {
-                    // single-byte message do not need running status
-                    self.status = None;
-
-                    // skip buffer for single-byte messages
-                    return Ok(Some(Packet::from_raw([CodeIndexNumber::from(status) as u8, byte, 0, 0])));
-                }
-                expected_len => {
-                    self.status = Some(status);
-                    self.buffer.clear(expected_len);
-                    self.buffer.push(byte);
-                }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parses Serial MIDI bytes straight into Messages
+/// Sysex is returned as fragments of up to 3 bytes, same as USB-MIDI
+#[derive(Debug, Default)]
+pub struct MessageParser {
+    parser: ByteParser,
+}
+
+impl MessageParser {
+    pub fn new(mode: ParserMode) -> Self {
+        MessageParser { parser: ByteParser::new(mode) }
+    }
+
+    /// Parser that reports malformed input as errors
+    pub fn strict() -> Self {
+        Self::new(ParserMode::Strict)
+    }
+
+    pub fn stats(&self) -> ParserStats {
+        self.parser.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.parser.stats = ParserStats::default();
+    }
+
+    /// Push new byte
+    /// returns:
+    /// - Ok(None) if message is incomplete
+    /// - Ok(Some(message)) if message is complete
+    /// - Err(_) if message has invalid values or, in strict mode, if input was malformed. Parser is ready for next byte.
+    pub fn advance(&mut self, byte: u8) -> Result<Option<Message>, MidiError> {
+        match self.parser.advance(byte)? {
+            Some(message) => Ok(Some(Message::try_from(message.bytes())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Running status and error recovery, shared by all parsers
+#[derive(Debug, Default)]
+struct ByteParser {
+    /// Raw status byte, including channel
+    status: Option<u8>,
+    buffer: MessageBuffer,
+    mode: ParserMode,
+    stats: ParserStats,
+}
+
+impl ByteParser {
+    fn new(mode: ParserMode) -> Self {
+        ByteParser {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    fn advance(&mut self, byte: u8) -> Result<Option<MessageBuffer>, MidiError> {
+        if is_realtime(byte) {
+            // realtime messages can be interleaved anywhere, even inside sysex
+            // partial message and running status are preserved
+            return match Status::try_from(byte) {
+                Ok(_) => Ok(Some(MessageBuffer::single(byte))),
+                Err(err) => self.drop_byte(err),
+            };
+        }
+
+        if byte == SYSEX_END {
+            return self.end_sysex();
+        }
+
+        if is_non_status(byte) {
+            return self.data(byte);
+        }
+
+        self.start(byte)
+    }
+
+    fn data(&mut self, byte: u8) -> Result<Option<MessageBuffer>, MidiError> {
+        let Some(status) = self.status else {
+            return self.drop_byte(MidiError::InvalidStatus(byte));
+        };
+        if !self.buffer.is_started() && is_channel_status(status) {
+            // running status, repeat last
+            self.buffer.push(status)?;
+        }
+        self.buffer.push(byte)?;
+
+        if self.buffer.is_full() {
+            if !is_channel_status(status) && status != SYSEX_START {
+                // system common messages do not use running status
+                self.status = None;
             }
+            return Ok(Some(self.buffer.take()));
         }
         Ok(None)
     }
+
+    fn end_sysex(&mut self) -> Result<Option<MessageBuffer>, MidiError> {
+        if self.status != Some(SYSEX_START) {
+            // SYSEX_END without SYSEX_START still cancels running status
+            let truncated = self.interrupt();
+            self.stats.dropped_bytes = self.stats.dropped_bytes.saturating_add(1);
+            return self.report(truncated.or(Some(MidiError::InvalidStatus(SYSEX_END))), None);
+        }
+        self.status = None;
+        self.buffer.push(SYSEX_END)?;
+        Ok(Some(self.buffer.take()))
+    }
+
+    fn start(&mut self, byte: u8) -> Result<Option<MessageBuffer>, MidiError> {
+        let interrupted = self.interrupt();
+        let status = match Status::try_from(byte) {
+            Ok(status) => status,
+            Err(err) => {
+                // undefined system common
+                self.stats.dropped_bytes = self.stats.dropped_bytes.saturating_add(1);
+                return self.report(interrupted.or(Some(err)), None);
+            }
+        };
+
+        let mut message = None;
+        match status.expected_len() {
+            1 => {
+                // skip buffer for single-byte messages
+                message = Some(MessageBuffer::single(byte));
+            }
+            expected_len => {
+                self.status = Some(byte);
+                self.buffer.clear(expected_len);
+                self.buffer.push(byte)?;
+            }
+        }
+        self.report(interrupted, message)
+    }
+
+    /// Abandon current message, if any
+    /// Returns the error to report in strict mode
+    fn interrupt(&mut self) -> Option<MidiError> {
+        let status = self.status.take();
+        let started = self.buffer.is_started();
+        self.buffer.clear(0);
+        if status == Some(SYSEX_START) {
+            self.stats.aborted_sysex = self.stats.aborted_sysex.saturating_add(1);
+            Some(MidiError::SysexInterrupted)
+        } else if started {
+            self.stats.truncated_messages = self.stats.truncated_messages.saturating_add(1);
+            Some(MidiError::TruncatedMessage)
+        } else {
+            None
+        }
+    }
+
+    fn drop_byte(&mut self, err: MidiError) -> Result<Option<MessageBuffer>, MidiError> {
+        self.stats.dropped_bytes = self.stats.dropped_bytes.saturating_add(1);
+        self.report(Some(err), None)
+    }
+
+    fn report(&self, err: Option<MidiError>, message: Option<MessageBuffer>) -> Result<Option<MessageBuffer>, MidiError> {
+        match err {
+            Some(err) if self.mode == ParserMode::Strict => Err(err),
+            _ => Ok(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Channel, ChannelMode, Note, U7, U14};
+
+    fn parse(parser: &mut PacketParser, bytes: &[u8]) -> heapless::Vec<[u8; 4], 8> {
+        let mut packets = heapless::Vec::new();
+        for byte in bytes {
+            if let Some(packet) = parser.advance(*byte).unwrap() {
+                packets.push(packet.bytes().try_into().unwrap()).unwrap();
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn running_status() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0x90, 60, 100, 62, 100]);
+        assert_eq!(&packets[..], &[[0x09, 0x90, 60, 100], [0x09, 0x90, 62, 100]]);
+    }
+
+    #[test]
+    fn running_status_keeps_channel() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0xB3, 7, 100, 7, 90]);
+        assert_eq!(&packets[..], &[[0x0B, 0xB3, 7, 100], [0x0B, 0xB3, 7, 90]]);
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0xF3, 5, 6, 0x90, 60, 100]);
+        assert_eq!(&packets[..], &[[0x02, 0xF3, 5, 0], [0x09, 0x90, 60, 100]]);
+        assert_eq!(parser.stats().dropped_bytes, 1);
+    }
+
+    #[test]
+    fn realtime_inside_message() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0x90, 60, 0xF8, 100, 62, 0xFA, 100]);
+        assert_eq!(&packets[..], &[
+            [0x05, 0xF8, 0, 0],
+            [0x09, 0x90, 60, 100],
+            [0x05, 0xFA, 0, 0],
+            [0x09, 0x90, 62, 100],
+        ]);
+    }
+
+    #[test]
+    fn realtime_inside_sysex() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0xF0, 1, 0xF8, 2, 3, 0xFE, 4, 0xF7]);
+        assert_eq!(&packets[..], &[
+            [0x05, 0xF8, 0, 0],
+            [0x04, 0xF0, 1, 2],
+            [0x05, 0xFE, 0, 0],
+            [0x07, 3, 4, 0xF7],
+        ]);
+    }
+
+    #[test]
+    fn measure_end_cancels_running_status() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[0x90, 60, 100, 0xF9, 3, 62, 100, 0x90, 62, 100]);
+        assert_eq!(&packets[..], &[[0x09, 0x90, 60, 100], [0x02, 0xF9, 3, 0], [0x09, 0x90, 62, 100]]);
+        assert_eq!(parser.stats().dropped_bytes, 2);
+
+        let packets = parse(&mut parser, &[0x90, 60, 0xF9, 3, 100]);
+        assert_eq!(&packets[..], &[[0x02, 0xF9, 3, 0]]);
+        assert_eq!(parser.stats().truncated_messages, 1);
+    }
+
+    #[test]
+    fn lenient_resyncs() {
+        let mut parser = PacketParser::default();
+        let packets = parse(&mut parser, &[1, 2, 0x90, 60, 0xF0, 1, 0x80, 60, 0, 0xF7, 0xF4, 0xFD]);
+        assert_eq!(&packets[..], &[[0x08, 0x80, 60, 0]]);
+        let stats = parser.stats();
+        assert_eq!(stats.dropped_bytes, 5);
+        assert_eq!(stats.truncated_messages, 1);
+        assert_eq!(stats.aborted_sysex, 1);
+    }
+
+    #[test]
+    fn strict_reports_errors() {
+        let mut parser = PacketParser::strict();
+        assert!(matches!(parser.advance(0x3C), Err(MidiError::InvalidStatus(0x3C))));
+        assert!(matches!(parser.advance(0x90), Ok(None)));
+        assert!(matches!(parser.advance(0x3C), Ok(None)));
+        assert!(matches!(parser.advance(0xF0), Err(MidiError::TruncatedMessage)));
+        assert!(matches!(parser.advance(0x01), Ok(None)));
+        assert!(matches!(parser.advance(0x90), Err(MidiError::SysexInterrupted)));
+        assert!(matches!(parser.advance(0x3C), Ok(None)));
+        assert!(matches!(parser.advance(0x40), Ok(Some(_))));
+        assert!(matches!(parser.advance(0xF7), Err(MidiError::InvalidStatus(0xF7))));
+        assert!(matches!(parser.advance(0xF5), Err(MidiError::InvalidStatus(0xF5))));
+    }
+
+    #[test]
+    fn parse_messages() {
+        let mut parser = MessageParser::default();
+        let mut messages = heapless::Vec::<Message, 8>::new();
+        for byte in [0x95, 60, 100, 0xF8, 60, 0, 0xE2, 0x00, 0x40, 0xF0, 1, 0xF7, 0xB0, 126, 4] {
+            if let Some(message) = parser.advance(byte).unwrap() {
+                messages.push(message).unwrap();
+            }
+        }
+        assert!(matches!(messages[0], Message::NoteOn(Channel(5), Note::C4, U7(100))));
+        assert!(matches!(messages[1], Message::TimingClock));
+        assert!(matches!(messages[2], Message::NoteOn(Channel(5), Note::C4, U7(0))));
+        assert!(matches!(messages[3], Message::PitchBend(Channel(2), U14(0x2000))));
+        assert!(matches!(messages[4], Message::SysexSingleByte(1)));
+        assert!(matches!(messages[5], Message::ChannelMode(Channel(0), ChannelMode::MonoOn(U7(4)))));
+    }
 }
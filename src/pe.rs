@@ -0,0 +1,489 @@
+//! MIDI-CI Property Exchange
+//! Requests and replies carry a JSON header and property data, split in chunks each sent as a CI sysex message
+//! Bodies can be received with `SysexAssembler` and sent with `SysexPackets`, over USB or serial ports alike
+
+use crate::ci::{read_header, write_header, CiHeader, Muid};
+use crate::universal::{push, read_7bit, write_7bit};
+use crate::MidiError;
+use core::convert::TryFrom;
+use core::str;
+use heapless::Vec;
+
+/// Largest chunk of property data, limited by its 14-bit length field
+pub const MAX_CHUNK_LEN: usize = 0x3FFF;
+
+pub const STATUS_OK: &[u8] = br#"{"status":200}"#;
+pub const STATUS_NOT_FOUND: &[u8] = br#"{"status":404}"#;
+pub const STATUS_NOT_ALLOWED: &[u8] = br#"{"status":405}"#;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum PeKind {
+    Get = 0x34,
+    GetReply = 0x35,
+    Set = 0x36,
+    SetReply = 0x37,
+    Subscribe = 0x38,
+    SubscribeReply = 0x39,
+    Notify = 0x3F,
+}
+
+impl PeKind {
+    /// Kind of the reply expected for a request of this kind
+    pub fn reply(&self) -> Option<PeKind> {
+        match self {
+            PeKind::Get => Some(PeKind::GetReply),
+            PeKind::Set => Some(PeKind::SetReply),
+            PeKind::Subscribe => Some(PeKind::SubscribeReply),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u8> for PeKind {
+    type Error = MidiError;
+
+    fn try_from(sub_id2: u8) -> Result<Self, Self::Error> {
+        Ok(match sub_id2 {
+            0x34 => PeKind::Get,
+            0x35 => PeKind::GetReply,
+            0x36 => PeKind::Set,
+            0x37 => PeKind::SetReply,
+            0x38 => PeKind::Subscribe,
+            0x39 => PeKind::SubscribeReply,
+            0x3F => PeKind::Notify,
+            _ => return Err(MidiError::InvalidSysex),
+        })
+    }
+}
+
+/// A single Property Exchange message, holding one chunk of property data
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PeChunk<'a> {
+    pub kind: PeKind,
+    pub request_id: u8,
+    /// JSON header, only sent in the first chunk
+    pub header: &'a [u8],
+    pub chunk_count: u16,
+    /// Chunk number, starting at 1
+    pub chunk: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> PeChunk<'a> {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END
+    /// Returns Err(InvalidSysex) if body is not a Property Exchange message
+    pub fn decode(body: &'a [u8]) -> Result<(CiHeader, PeChunk<'a>), MidiError> {
+        let (ci, sub_id2, data) = read_header(body)?;
+        let kind = PeKind::try_from(sub_id2)?;
+        let (&request_id, data) = data.split_first().ok_or(MidiError::TruncatedMessage)?;
+        let (header, data) = read_block(data)?;
+        let (chunk_count, data) = read_7bit(data, 2)?;
+        let (chunk, data) = read_7bit(data, 2)?;
+        let (data, _) = read_block(data)?;
+        Ok((ci, PeChunk { kind, request_id, header, chunk_count: chunk_count as u16, chunk: chunk as u16, data }))
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode<const N: usize>(&self, ci: &CiHeader, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        write_header(ci, self.kind as u8, buf)?;
+        push(buf, &[self.request_id & 0x7F])?;
+        write_block(buf, self.header)?;
+        write_7bit(buf, self.chunk_count as u32, 2)?;
+        write_7bit(buf, self.chunk as u32, 2)?;
+        write_block(buf, self.data)
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.chunk >= self.chunk_count
+    }
+}
+
+/// Read 14-bit length and following bytes
+fn read_block(data: &[u8]) -> Result<(&[u8], &[u8]), MidiError> {
+    let (len, data) = read_7bit(data, 2)?;
+    let len = len as usize;
+    let block = data.get(..len).ok_or(MidiError::TruncatedMessage)?;
+    Ok((block, &data[len..]))
+}
+
+fn write_block<const N: usize>(buf: &mut Vec<u8, N>, block: &[u8]) -> Result<(), MidiError> {
+    write_7bit(buf, block.len() as u32, 2)?;
+    push(buf, block)
+}
+
+/// Splits a Property Exchange message into chunks of up to `max_len` bytes of property data
+#[derive(Debug, Clone)]
+pub struct PeChunks<'a> {
+    kind: PeKind,
+    request_id: u8,
+    header: &'a [u8],
+    data: &'a [u8],
+    max_len: usize,
+    chunk: u16,
+}
+
+impl<'a> PeChunks<'a> {
+    pub fn new(kind: PeKind, request_id: u8, header: &'a [u8], data: &'a [u8], max_len: usize) -> Self {
+        PeChunks { kind, request_id, header, data, max_len: max_len.clamp(1, MAX_CHUNK_LEN), chunk: 0 }
+    }
+
+    pub fn chunk_count(&self) -> u16 {
+        self.data.len().div_ceil(self.max_len).max(1) as u16
+    }
+}
+
+impl<'a> Iterator for PeChunks<'a> {
+    type Item = PeChunk<'a>;
+
+    fn next(&mut self) -> Option<PeChunk<'a>> {
+        let chunk_count = self.chunk_count();
+        if self.chunk >= chunk_count {
+            return None;
+        }
+        let start = self.chunk as usize * self.max_len;
+        let end = (start + self.max_len).min(self.data.len());
+        self.chunk += 1;
+        Some(PeChunk {
+            kind: self.kind,
+            request_id: self.request_id,
+            header: if self.chunk == 1 { self.header } else { &[] },
+            chunk_count,
+            chunk: self.chunk,
+            data: &self.data[start..end],
+        })
+    }
+}
+
+/// A complete Property Exchange message
+#[derive(Debug)]
+pub struct PeMessage<const H: usize, const D: usize> {
+    pub source: Muid,
+    pub kind: PeKind,
+    pub request_id: u8,
+    pub header: Vec<u8, H>,
+    pub data: Vec<u8, D>,
+}
+
+impl<const H: usize, const D: usize> PeMessage<H, D> {
+    /// Value of the header's "resource" field
+    pub fn resource(&self) -> Option<&str> {
+        header_field(&self.header, "resource")
+    }
+}
+
+/// Extract the value of a string field from a flat JSON header
+/// This is a naive scan, not a JSON parser: escaped quotes and nested objects or arrays are not supported
+pub fn header_field<'a>(header: &'a [u8], name: &str) -> Option<&'a str> {
+    let header = str::from_utf8(header).ok()?;
+    let mut rest = header;
+    while let Some(pos) = rest.find('"') {
+        rest = &rest[pos + 1..];
+        let end = rest.find('"')?;
+        let key = &rest[..end];
+        rest = rest[end + 1..].trim_start();
+        let Some(value) = rest.strip_prefix(':') else {
+            // a string value, skip it
+            continue;
+        };
+        let value = value.trim_start();
+        if key == name {
+            let value = value.strip_prefix('"')?;
+            return Some(&value[..value.find('"')?]);
+        }
+        rest = value;
+    }
+    None
+}
+
+struct PendingMessage<const H: usize, const D: usize> {
+    message: PeMessage<H, D>,
+    next_chunk: u16,
+}
+
+/// Reassembles chunked Property Exchange messages, up to R in flight
+/// Headers are limited to H bytes and property data to D bytes
+pub struct PeAssembler<const R: usize, const H: usize, const D: usize> {
+    pending: Vec<PendingMessage<H, D>, R>,
+}
+
+impl<const R: usize, const H: usize, const D: usize> Default for PeAssembler<R, H, D> {
+    fn default() -> Self {
+        PeAssembler { pending: Vec::new() }
+    }
+}
+
+impl<const R: usize, const H: usize, const D: usize> PeAssembler<R, H, D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop all partially received messages
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Push next chunk
+    /// returns:
+    /// - Ok(None) if message is incomplete, or its first chunk was missed
+    /// - Ok(Some(message)) if message is complete
+    /// - Err(BufferFull) if R messages are already in flight
+    /// - Err(SysexOutOfBounds) if header or data exceed capacity, message is dropped
+    /// - Err(SysexInterrupted) if a chunk was skipped, message is dropped
+    pub fn advance(&mut self, ci: &CiHeader, chunk: &PeChunk) -> Result<Option<PeMessage<H, D>>, MidiError> {
+        let found = self.pending.iter().position(|p| p.message.source == ci.source && p.message.request_id == chunk.request_id);
+        let index = match found {
+            _ if chunk.chunk == 1 => {
+                // new message replaces any unfinished one with same request id
+                if let Some(index) = found {
+                    self.pending.swap_remove(index);
+                }
+                let message = PeMessage { source: ci.source, kind: chunk.kind, request_id: chunk.request_id, header: Vec::new(), data: Vec::new() };
+                self.pending.push(PendingMessage { message, next_chunk: 1 }).map_err(|_| MidiError::BufferFull)?;
+                self.pending.len() - 1
+            }
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let pending = &mut self.pending[index];
+        if chunk.chunk != pending.next_chunk {
+            self.pending.swap_remove(index);
+            return Err(MidiError::SysexInterrupted);
+        }
+        if pending.message.header.extend_from_slice(chunk.header).is_err() || pending.message.data.extend_from_slice(chunk.data).is_err() {
+            self.pending.swap_remove(index);
+            return Err(MidiError::SysexOutOfBounds);
+        }
+        pending.next_chunk += 1;
+        if chunk.is_last() {
+            return Ok(Some(self.pending.swap_remove(index).message));
+        }
+        Ok(None)
+    }
+}
+
+/// A request sent and awaiting reply
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PeTransaction {
+    pub kind: PeKind,
+    pub destination: Muid,
+    pub request_id: u8,
+}
+
+/// Allocates request ids for outgoing Get, Set and Subscribe requests and matches their replies, up to R in flight
+#[derive(Debug, Default)]
+pub struct PeRequests<const R: usize> {
+    pending: Vec<PeTransaction, R>,
+    next_id: u8,
+}
+
+impl<const R: usize> PeRequests<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pending(&self) -> &[PeTransaction] {
+        &self.pending
+    }
+
+    /// Start a new request, returns its request id
+    /// Err(BufferFull) if R requests, or all 128 request ids, are already in flight
+    pub fn begin(&mut self, kind: PeKind, destination: Muid) -> Result<u8, MidiError> {
+        if kind.reply().is_none() || self.pending.is_full() {
+            return Err(MidiError::BufferFull);
+        }
+        let request_id = (0..128u8)
+            .map(|offset| (self.next_id + offset) & 0x7F)
+            .find(|id| self.pending.iter().all(|t| t.request_id != *id))
+            .ok_or(MidiError::BufferFull)?;
+        self.next_id = (request_id + 1) & 0x7F;
+        let _ = self.pending.push(PeTransaction { kind, destination, request_id });
+        Ok(request_id)
+    }
+
+    /// Match a received reply with its request, which is then completed
+    pub fn complete(&mut self, source: Muid, kind: PeKind, request_id: u8) -> Option<PeTransaction> {
+        let index = self.pending.iter().position(|t| t.request_id == request_id && t.destination == source && t.kind.reply() == Some(kind))?;
+        Some(self.pending.swap_remove(index))
+    }
+
+    /// Give up waiting for a reply
+    pub fn cancel(&mut self, request_id: u8) {
+        self.pending.retain(|t| t.request_id != request_id);
+    }
+}
+
+/// Source of property data served by `PeResponder`
+pub trait PropertyHandler {
+    /// Returns JSON data of resource, None if resource is unknown
+    fn get(&self, resource: &str) -> Option<&[u8]>;
+
+    /// Returns true if new data was accepted
+    fn set(&mut self, _resource: &str, _data: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Read-only properties from a static table of resource names and JSON data, e.g. ResourceList and DeviceInfo
+impl<'a> PropertyHandler for &'a [(&'a str, &'a [u8])] {
+    fn get(&self, resource: &str) -> Option<&[u8]> {
+        self.iter().find(|(name, _)| *name == resource).map(|(_, data)| *data)
+    }
+}
+
+/// Answers Property Exchange requests addressed to our MUID
+/// Up to R requests may be in flight, with headers of up to H bytes and data of up to D bytes
+pub struct PeResponder<P, const R: usize, const H: usize, const D: usize> {
+    muid: Muid,
+    handler: P,
+    assembler: PeAssembler<R, H, D>,
+    max_chunk_len: usize,
+}
+
+impl<P: PropertyHandler, const R: usize, const H: usize, const D: usize> PeResponder<P, R, H, D> {
+    /// `muid` must be the one used by `CiResponder`
+    pub fn new(muid: Muid, handler: P) -> Self {
+        PeResponder { muid, handler, assembler: PeAssembler::new(), max_chunk_len: 256 }
+    }
+
+    /// Largest chunk of property data to send, should fit in the max sysex size advertised by the requester
+    pub fn with_max_chunk_len(mut self, max_chunk_len: usize) -> Self {
+        self.max_chunk_len = max_chunk_len;
+        self
+    }
+
+    pub fn set_muid(&mut self, muid: Muid) {
+        self.muid = muid;
+        self.assembler.reset();
+    }
+
+    pub fn handler(&mut self) -> &mut P {
+        &mut self.handler
+    }
+
+    /// Push next received sysex body
+    /// Returns the chunks of the reply to send once a request is complete
+    /// Messages that are not Property Exchange requests addressed to our MUID are ignored
+    pub fn advance(&mut self, body: &[u8]) -> Option<(CiHeader, PeChunks<'_>)> {
+        let (ci, chunk) = PeChunk::decode(body).ok()?;
+        if ci.destination != self.muid || chunk.kind.reply().is_none() {
+            return None;
+        }
+        let request = self.assembler.advance(&ci, &chunk).ok()??;
+        let resource = request.resource().unwrap_or_default();
+        let (header, data): (&[u8], &[u8]) = match request.kind {
+            PeKind::Get => match self.handler.get(resource) {
+                Some(data) => (STATUS_OK, data),
+                None => (STATUS_NOT_FOUND, &[]),
+            },
+            PeKind::Set if self.handler.set(resource, &request.data) => (STATUS_OK, &[]),
+            PeKind::Set => (STATUS_NOT_ALLOWED, &[]),
+            _ => match self.handler.get(resource) {
+                Some(_) => (STATUS_OK, &[]),
+                None => (STATUS_NOT_FOUND, &[]),
+            },
+        };
+        let kind = request.kind.reply()?;
+        Some((ci.reply(self.muid), PeChunks::new(kind, request.request_id, header, data, self.max_chunk_len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SysexAssembler, SysexPackets};
+
+    const DEVICE_INFO: &[u8] = br#"{"manufacturerId":[125,0,0],"familyId":[0,0],"modelId":[1,0],"versionId":[0,0,0,1]}"#;
+    const PROPERTIES: &[(&str, &[u8])] = &[
+        ("ResourceList", br#"[{"resource":"DeviceInfo"}]"#),
+        ("DeviceInfo", DEVICE_INFO),
+    ];
+
+    /// Encode chunk and send it through USB-MIDI packets
+    fn transmit(ci: &CiHeader, chunk: &PeChunk) -> Vec<u8, 128> {
+        let mut body: Vec<u8, 128> = Vec::new();
+        chunk.encode(ci, &mut body).unwrap();
+        let mut assembler = SysexAssembler::<128>::new();
+        SysexPackets::new(&body, 0).find_map(|packet| assembler.advance(packet).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn header() {
+        let header = br#"{"status": 200, "resource" : "DeviceInfo", "resId":"x"}"#;
+        assert_eq!(header_field(header, "resource"), Some("DeviceInfo"));
+        assert_eq!(header_field(header, "resId"), Some("x"));
+        assert_eq!(header_field(header, "missing"), None);
+    }
+
+    #[test]
+    fn get() {
+        let mut requests = PeRequests::<2>::new();
+        let mut responder = PeResponder::<_, 2, 64, 16>::new(0x42, PROPERTIES).with_max_chunk_len(20);
+        let mut assembler = PeAssembler::<2, 64, 128>::new();
+
+        let request_id = requests.begin(PeKind::Get, 0x42).unwrap();
+        let header = br#"{"resource":"DeviceInfo"}"#;
+        let ci = CiHeader::new(0x99, 0x42);
+        let mut reply = None;
+        for chunk in PeChunks::new(PeKind::Get, request_id, header, &[], 20) {
+            reply = responder.advance(&transmit(&ci, &chunk));
+        }
+        let (reply_ci, chunks) = reply.unwrap();
+        assert!(chunks.chunk_count() > 1);
+
+        let mut complete = None;
+        for chunk in chunks {
+            let body = transmit(&reply_ci, &chunk);
+            let (ci, chunk) = PeChunk::decode(&body).unwrap();
+            complete = assembler.advance(&ci, &chunk).unwrap();
+        }
+        let message = complete.unwrap();
+        assert_eq!(message.header.as_slice(), STATUS_OK);
+        assert_eq!(message.data.as_slice(), DEVICE_INFO);
+        assert!(requests.complete(message.source, message.kind, message.request_id).is_some());
+        assert!(requests.pending().is_empty());
+    }
+
+    #[test]
+    fn request_ids_exhausted() {
+        let mut requests = PeRequests::<130>::new();
+        for expected in 0..128 {
+            assert_eq!(requests.begin(PeKind::Get, 0x42).unwrap(), expected);
+        }
+        assert!(matches!(requests.begin(PeKind::Get, 0x42), Err(MidiError::BufferFull)));
+        requests.cancel(5);
+        assert_eq!(requests.begin(PeKind::Get, 0x42).unwrap(), 5);
+    }
+
+    #[test]
+    fn set_read_only() {
+        let mut responder = PeResponder::<_, 1, 64, 16>::new(0x42, PROPERTIES);
+        let chunk = PeChunks::new(PeKind::Set, 1, br#"{"resource":"DeviceInfo"}"#, b"{}", 64).next().unwrap();
+        let (_, mut reply) = responder.advance(&transmit(&CiHeader::new(0x99, 0x42), &chunk)).unwrap();
+        assert_eq!(reply.next().unwrap().header, STATUS_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn not_found() {
+        let mut responder = PeResponder::<_, 1, 64, 16>::new(0x42, PROPERTIES);
+        let chunk = PeChunks::new(PeKind::Get, 2, br#"{"resource":"ChannelList"}"#, &[], 64).next().unwrap();
+        let (_, mut reply) = responder.advance(&transmit(&CiHeader::new(0x99, 0x42), &chunk)).unwrap();
+        let reply = reply.next().unwrap();
+        assert_eq!(reply.header, STATUS_NOT_FOUND);
+        assert!(reply.data.is_empty());
+    }
+
+    #[test]
+    fn skipped_chunk() {
+        let mut assembler = PeAssembler::<1, 8, 8>::new();
+        let ci = CiHeader::new(0x99, 0x42);
+        let mut chunks = PeChunks::new(PeKind::Notify, 3, b"{}", b"abcdef", 2);
+        assert!(assembler.advance(&ci, &chunks.next().unwrap()).unwrap().is_none());
+        chunks.next();
+        assert!(assembler.advance(&ci, &chunks.next().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! Scala tuning files
+//! `.scl` files list the pitches of a scale, `.kbm` files map the scale degrees to keys
+//! Lines starting with '!' are comments, malformed lines are reported as Err(ParseError(line number))
+
+use crate::mts::{NoteTuning, TuningTable};
+use crate::{MidiError, Note};
+use heapless::Vec;
+use num::Float;
+
+/// Most pitches in a scale, and most entries in a keyboard mapping
+pub const MAX_PITCHES: usize = 128;
+
+const NOTES: usize = 128;
+
+/// Non-comment lines with their line number, starting at 1
+fn lines(text: &str) -> impl Iterator<Item = (u32, &str)> {
+    text.lines().zip(1..).map(|(line, number)| (number, line.trim())).filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// First word of next non-blank line
+fn next_value<'a>(lines: &mut impl Iterator<Item = (u32, &'a str)>, last: &mut u32) -> Result<(u32, &'a str), MidiError> {
+    let (number, line) = lines.find(|(_, line)| !line.is_empty()).ok_or(MidiError::ParseError(*last + 1))?;
+    *last = number;
+    Ok((number, line.split_whitespace().next().unwrap_or_default()))
+}
+
+/// Key number of next non-blank line
+fn next_key<'a>(lines: &mut impl Iterator<Item = (u32, &'a str)>, last: &mut u32) -> Result<u8, MidiError> {
+    let (number, value) = next_value(lines, last)?;
+    match parse(number, value)? {
+        key @ 0..=127 => Ok(key),
+        _ => Err(MidiError::ParseError(number)),
+    }
+}
+
+fn parse<T: core::str::FromStr>(number: u32, value: &str) -> Result<T, MidiError> {
+    value.parse().map_err(|_| MidiError::ParseError(number))
+}
+
+/// Pitch in cents of a `.scl` line, either cents with a period or a ratio of integers
+fn parse_pitch(number: u32, value: &str) -> Result<f32, MidiError> {
+    if value.contains('.') {
+        return parse(number, value);
+    }
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let numerator: u32 = parse(number, numerator)?;
+    let denominator: u32 = parse(number, denominator)?;
+    if numerator == 0 || denominator == 0 {
+        return Err(MidiError::ParseError(number));
+    }
+    Ok(1200.0 * Float::log2(numerator as f32 / denominator as f32))
+}
+
+/// Scale from a `.scl` file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale<'a> {
+    description: &'a str,
+    /// Cents of degrees 1 to N, the last one is the period, usually an octave
+    pitches: Vec<f32, MAX_PITCHES>,
+}
+
+impl<'a> Scale<'a> {
+    pub fn parse(scl: &'a str) -> Result<Self, MidiError> {
+        let mut lines = lines(scl);
+        // the description may be blank
+        let (_, description) = lines.next().ok_or(MidiError::ParseError(1))?;
+        let mut last = 1;
+        let (number, count) = next_value(&mut lines, &mut last)?;
+        let count: usize = parse(number, count)?;
+        if count == 0 || count > MAX_PITCHES {
+            return Err(MidiError::ParseError(number));
+        }
+        let mut pitches = Vec::new();
+        while pitches.len() < count {
+            let (number, value) = next_value(&mut lines, &mut last)?;
+            pitches.push(parse_pitch(number, value)?).map_err(|_| MidiError::ParseError(number))?;
+        }
+        Ok(Scale { description, pitches })
+    }
+
+    pub fn description(&self) -> &'a str {
+        self.description
+    }
+
+    /// Number of degrees, including the period
+    pub fn len(&self) -> usize {
+        self.pitches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pitches.is_empty()
+    }
+
+    /// Interval repeating the scale, in cents
+    pub fn period(&self) -> f32 {
+        self.pitches.last().copied().unwrap_or(1200.0)
+    }
+
+    /// Cents above degree 0 of any degree, repeating the scale by its period
+    pub fn cents(&self, degree: i32) -> f32 {
+        let len = self.pitches.len() as i32;
+        let base = match degree.rem_euclid(len) {
+            0 => 0.0,
+            index => self.pitches[index as usize - 1],
+        };
+        degree.div_euclid(len) as f32 * self.period() + base
+    }
+}
+
+/// Keyboard mapping from a `.kbm` file
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    /// Keys in a repetition of the mapping, 0 maps keys linearly to scale degrees
+    pub size: u8,
+    /// Range of keys to retune
+    pub first: u8,
+    pub last: u8,
+    /// Key mapped to scale degree 0
+    pub middle: u8,
+    /// Key tuned to `frequency`
+    pub reference: u8,
+    pub frequency: f32,
+    /// Scale degree spanned by each repetition of the mapping, 0 for the scale's period
+    pub octave_degree: u16,
+    /// Scale degree of each key of a repetition, None if key is not mapped
+    pub map: Vec<Option<u16>, MAX_PITCHES>,
+}
+
+/// Linear mapping, with degree 0 on middle C and A4 at 440Hz
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        KeyboardMapping {
+            size: 0,
+            first: 0,
+            last: 127,
+            middle: Note::C4 as u8,
+            reference: Note::A4 as u8,
+            frequency: 440.0,
+            octave_degree: 0,
+            map: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub fn parse(kbm: &str) -> Result<Self, MidiError> {
+        let mut lines = lines(kbm);
+        let mut last = 0;
+        let size = next_key(&mut lines, &mut last)?;
+        let first = next_key(&mut lines, &mut last)?;
+        let last_key = next_key(&mut lines, &mut last)?;
+        let middle = next_key(&mut lines, &mut last)?;
+        let reference = next_key(&mut lines, &mut last)?;
+        let (number, frequency) = next_value(&mut lines, &mut last)?;
+        let frequency: f32 = parse(number, frequency)?;
+        if frequency <= 0.0 {
+            return Err(MidiError::ParseError(number));
+        }
+        let (number, octave_degree) = next_value(&mut lines, &mut last)?;
+        let octave_degree = parse(number, octave_degree)?;
+
+        // missing entries at the end are unmapped
+        let mut map = Vec::new();
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()).take(size as usize) {
+            let entry = match line.split_whitespace().next().unwrap_or_default() {
+                "x" | "X" => None,
+                degree => Some(parse(number, degree)?),
+            };
+            map.push(entry).map_err(|_| MidiError::ParseError(number))?;
+        }
+        Ok(KeyboardMapping { size, first, last: last_key, middle, reference, frequency, octave_degree, map })
+    }
+
+    /// Cents of key above degree 0, None if key is not mapped
+    fn cents(&self, scale: &Scale, key: u8) -> Option<f32> {
+        let offset = key as i32 - self.middle as i32;
+        if self.size == 0 {
+            return Some(scale.cents(offset));
+        }
+        let size = self.size as i32;
+        let degree = (*self.map.get(offset.rem_euclid(size) as usize)?)?;
+        let octave = match self.octave_degree {
+            0 => scale.period(),
+            degree => scale.cents(degree as i32),
+        };
+        Some(offset.div_euclid(size) as f32 * octave + scale.cents(degree as i32))
+    }
+}
+
+/// Frequency in Hz of each note, None for unmapped notes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrequencyTable {
+    frequencies: [Option<f32>; NOTES],
+}
+
+impl FrequencyTable {
+    /// Tune notes of the mapping's range to the scale
+    /// An unmapped reference key is tuned as if the mapping were linear
+    pub fn new(scale: &Scale, mapping: &KeyboardMapping) -> Self {
+        let reference = mapping
+            .cents(scale, mapping.reference)
+            .unwrap_or_else(|| scale.cents(mapping.reference as i32 - mapping.middle as i32));
+        let frequencies = core::array::from_fn(|key| {
+            let key = key as u8;
+            if key < mapping.first || key > mapping.last {
+                return None;
+            }
+            let cents = mapping.cents(scale, key)?;
+            Some(mapping.frequency * Float::exp2((cents - reference) / 1200.0))
+        });
+        FrequencyTable { frequencies }
+    }
+
+    /// None for unmapped notes and notes above the MIDI range
+    pub fn frequency(&self, note: Note) -> Option<f32> {
+        self.frequencies.get(note as usize).copied().flatten()
+    }
+
+    /// Pitch of note in cents above note 0 (C-1) of equal temperament with A4 at 440Hz
+    pub fn cents(&self, note: Note) -> Option<f32> {
+        let frequency = self.frequency(note)?;
+        Some((Note::A4 as u8) as f32 * 100.0 + 1200.0 * Float::log2(frequency / 440.0))
+    }
+
+    /// Tuning table for an MTS bulk dump, unmapped notes keep their equal temperament tuning
+    pub fn tuning_table(&self) -> TuningTable {
+        let mut table = TuningTable::new();
+        for key in 0..NOTES as u8 {
+            let Ok(note) = Note::try_from(key) else { continue };
+            if let Some(cents) = self.cents(note) {
+                table.set(note, NoteTuning::from_cents(cents));
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JUST: &str = "! just.scl
+!
+5-limit just intonation
+ 12
+!
+ 16/15
+ 9/8
+ 6/5
+ 5/4
+ 4/3
+ 45/32
+ 3/2
+ 8/5
+ 5/3
+ 9/5
+ 15/8
+ 2/1
+";
+
+    const EQUAL: &str = "! 12tet.scl
+12-tone equal temperament
+12
+100.0
+200.
+300.0
+400.0
+500.0
+600.0
+700.0
+800.0
+900.0
+1000.0
+1100.0
+2/1
+";
+
+    const WHITE_KEYS: &str = "! white keys only, C4 at 256Hz
+12
+0
+127
+60
+60
+256.0
+7
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn scales() {
+        let scale = Scale::parse(JUST).unwrap();
+        assert_eq!(scale.description(), "5-limit just intonation");
+        assert_eq!(scale.len(), 12);
+        assert!(close(scale.cents(7), 701.955));
+        assert!(close(scale.cents(-5), 701.955 - 1200.0));
+
+        let table = FrequencyTable::new(&Scale::parse(EQUAL).unwrap(), &KeyboardMapping::default());
+        assert!(close(table.frequency(Note::A4).unwrap(), 440.0));
+        assert!(close(table.frequency(Note::C4).unwrap(), 261.626));
+        assert_eq!(table.tuning_table(), TuningTable::new());
+    }
+
+    #[test]
+    fn mapping() {
+        let mapping = KeyboardMapping::parse(WHITE_KEYS).unwrap();
+        assert_eq!(mapping.map.len(), 12);
+        let scale = Scale::parse("!\n\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1").unwrap();
+        let table = FrequencyTable::new(&scale, &mapping);
+        assert_eq!(table.frequency(Note::C4), Some(256.0));
+        assert_eq!(table.frequency(Note::Cs4), None);
+        assert_eq!(table.frequency(Note::Gs9), None);
+        assert!(close(table.frequency(Note::G4).unwrap(), 384.0));
+        assert!(close(table.frequency(Note::C5).unwrap(), 512.0));
+        assert!(close(table.frequency(Note::B3).unwrap(), 240.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Scale::parse("!\nbad\n2\n100.0\n3/0\n"), Err(MidiError::ParseError(5))));
+        assert!(matches!(Scale::parse("!\nshort\n3\n100.0\n"), Err(MidiError::ParseError(5))));
+        assert!(matches!(Scale::parse("!\nnotes\nmany\n"), Err(MidiError::ParseError(3))));
+        assert!(matches!(KeyboardMapping::parse("0\n0\n200\n"), Err(MidiError::ParseError(3))));
+    }
+}
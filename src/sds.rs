@@ -0,0 +1,842 @@
+//! Sample Dump Standard
+//! Universal Non-Realtime messages carrying sample words in 120-byte data packets,
+//! acknowledged one by one unless the receiver stays silent, in which case transfer runs open loop
+
+use crate::universal::{push, read_7bit, write_7bit, NON_REALTIME};
+use crate::{transmit_sysex, CableNumber, MidiError, Packet, Receive, SysexAssembler, Transmit, U14, U7};
+use heapless::Vec;
+
+const DUMP_HEADER: u8 = 0x01;
+const DATA_PACKET: u8 = 0x02;
+const DUMP_REQUEST: u8 = 0x03;
+const EOF: u8 = 0x7B;
+const WAIT: u8 = 0x7C;
+const CANCEL: u8 = 0x7D;
+const NAK: u8 = 0x7E;
+const ACK: u8 = 0x7F;
+
+/// Data bytes in a data packet
+pub const PACKET_LEN: usize = 120;
+/// Largest SDS body, a data packet
+pub const MAX_BODY_LEN: usize = 3 + 1 + PACKET_LEN + 1;
+
+/// Time in ms the sender waits for the dump header to be acknowledged before going open loop
+const HEADER_TIMEOUT: u32 = 2000;
+/// Time in ms the sender waits for a data packet to be acknowledged before sending the next one
+const PACKET_TIMEOUT: u32 = 20;
+
+/// Supported sample word sizes
+const BITS: core::ops::RangeInclusive<u8> = 8..=28;
+
+/// Number of 7-bit bytes holding a sample word
+pub fn bytes_per_word(bits: u8) -> usize {
+    (bits as usize).div_ceil(7)
+}
+
+/// Number of sample words held in a data packet
+pub fn words_per_packet(bits: u8) -> usize {
+    PACKET_LEN / bytes_per_word(bits)
+}
+
+/// Pack unsigned sample word into 7-bit bytes, most significant bits first and left-justified
+pub fn pack_word(word: u32, bits: u8, out: &mut [u8]) {
+    let len = bytes_per_word(bits);
+    let word = word << (len * 7 - bits as usize);
+    for (i, byte) in out[..len].iter_mut().enumerate() {
+        *byte = (word >> ((len - 1 - i) * 7)) as u8 & 0x7F;
+    }
+}
+
+/// Unpack unsigned sample word from 7-bit bytes
+pub fn unpack_word(bytes: &[u8], bits: u8) -> u32 {
+    let len = bytes_per_word(bits);
+    let word = bytes[..len].iter().fold(0, |acc, byte| acc << 7 | (byte & 0x7F) as u32);
+    word >> (len * 7 - bits as usize)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum LoopType {
+    Forward = 0x00,
+    Alternating = 0x01,
+    Off = 0x7F,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DumpHeader {
+    pub sample_number: U14,
+    /// Significant bits per sample word, 8 to 28
+    pub bits: u8,
+    /// Sample period in ns
+    pub period: u32,
+    /// Sample length, loop start and loop end, in words
+    pub length: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub loop_type: LoopType,
+}
+
+impl DumpHeader {
+    fn read(data: &[u8]) -> Result<Self, MidiError> {
+        let [lsb, msb, bits, data @ ..] = data else {
+            return Err(MidiError::TruncatedMessage);
+        };
+        if !BITS.contains(bits) {
+            return Err(MidiError::InvalidSysex);
+        }
+        let (period, data) = read_7bit(data, 3)?;
+        let (length, data) = read_7bit(data, 3)?;
+        let (loop_start, data) = read_7bit(data, 3)?;
+        let (loop_end, data) = read_7bit(data, 3)?;
+        let loop_type = match data.first().ok_or(MidiError::TruncatedMessage)? {
+            0x00 => LoopType::Forward,
+            0x01 => LoopType::Alternating,
+            _ => LoopType::Off,
+        };
+        Ok(DumpHeader { sample_number: U14::try_from((*lsb, *msb))?, bits: *bits, period, length, loop_start, loop_end, loop_type })
+    }
+
+    fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let (lsb, msb) = <(U7, U7)>::from(self.sample_number);
+        push(buf, &[lsb.0, msb.0, self.bits])?;
+        for value in [self.period, self.length, self.loop_start, self.loop_end] {
+            write_7bit(buf, value, 3)?;
+        }
+        push(buf, &[self.loop_type as u8])
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DataPacket {
+    /// Running packet number, wrapping from 127 to 0
+    pub number: u8,
+    pub data: [u8; PACKET_LEN],
+    /// False if received checksum did not match
+    pub valid: bool,
+}
+
+impl DataPacket {
+    pub fn new(number: u8, data: [u8; PACKET_LEN]) -> Self {
+        DataPacket { number: number & 0x7F, data, valid: true }
+    }
+
+    /// XOR of all bytes from sub-id to last data byte
+    fn checksum(&self, device_id: U7) -> u8 {
+        let header = NON_REALTIME ^ device_id.0 ^ DATA_PACKET ^ self.number;
+        self.data.iter().fold(header, |acc, byte| acc ^ byte) & 0x7F
+    }
+
+    /// First `count` sample words of the packet
+    pub fn words(&self, bits: u8, count: usize) -> SampleWords {
+        SampleWords { data: self.data, bits, index: 0, count: count.min(words_per_packet(bits)) }
+    }
+}
+
+/// Sample words unpacked from a data packet
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SampleWords {
+    data: [u8; PACKET_LEN],
+    bits: u8,
+    index: usize,
+    count: usize,
+}
+
+impl Iterator for SampleWords {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        let len = bytes_per_word(self.bits);
+        let word = unpack_word(&self.data[self.index * len..], self.bits);
+        self.index += 1;
+        Some(word)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdsMessage {
+    DumpHeader(DumpHeader),
+    DataPacket(DataPacket),
+    DumpRequest(U14),
+    /// Handshakes carry the number of the packet they refer to
+    Ack(u8),
+    Nak(u8),
+    Cancel(u8),
+    Wait(u8),
+    Eof(u8),
+}
+
+impl SdsMessage {
+    /// Decode sysex body, excluding SYSEX_START and SYSEX_END, returns device id and message
+    /// Returns Err(InvalidSysex) if body is not a Sample Dump message
+    pub fn decode(body: &[u8]) -> Result<(U7, SdsMessage), MidiError> {
+        let [NON_REALTIME, device_id, kind, data @ ..] = body else {
+            return Err(MidiError::InvalidSysex);
+        };
+        let device_id = U7(device_id & 0x7F);
+        let number = || data.first().map(|number| number & 0x7F).ok_or(MidiError::TruncatedMessage);
+        let message = match *kind {
+            DUMP_HEADER => SdsMessage::DumpHeader(DumpHeader::read(data)?),
+            DATA_PACKET => {
+                let [number, payload @ .., checksum] = data else {
+                    return Err(MidiError::TruncatedMessage);
+                };
+                let data: [u8; PACKET_LEN] = payload.try_into().map_err(|_| MidiError::TruncatedMessage)?;
+                let mut packet = DataPacket::new(*number, data);
+                packet.valid = packet.checksum(device_id) == *checksum;
+                SdsMessage::DataPacket(packet)
+            }
+            DUMP_REQUEST => match data {
+                [lsb, msb, ..] => SdsMessage::DumpRequest(U14::try_from((*lsb, *msb))?),
+                _ => return Err(MidiError::TruncatedMessage),
+            },
+            ACK => SdsMessage::Ack(number()?),
+            NAK => SdsMessage::Nak(number()?),
+            CANCEL => SdsMessage::Cancel(number()?),
+            WAIT => SdsMessage::Wait(number()?),
+            EOF => SdsMessage::Eof(number()?),
+            _ => return Err(MidiError::InvalidSysex),
+        };
+        Ok((device_id, message))
+    }
+
+    /// Encode as sysex body, excluding SYSEX_START and SYSEX_END
+    pub fn encode<const N: usize>(&self, device_id: U7, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let (kind, number) = match self {
+            SdsMessage::DumpHeader(header) => {
+                push(buf, &[NON_REALTIME, device_id.0, DUMP_HEADER])?;
+                return header.write(buf);
+            }
+            SdsMessage::DataPacket(packet) => {
+                push(buf, &[NON_REALTIME, device_id.0, DATA_PACKET, packet.number])?;
+                push(buf, &packet.data)?;
+                return push(buf, &[packet.checksum(device_id)]);
+            }
+            SdsMessage::DumpRequest(sample_number) => {
+                let (lsb, msb) = <(U7, U7)>::from(*sample_number);
+                return push(buf, &[NON_REALTIME, device_id.0, DUMP_REQUEST, lsb.0, msb.0]);
+            }
+            SdsMessage::Ack(number) => (ACK, number),
+            SdsMessage::Nak(number) => (NAK, number),
+            SdsMessage::Cancel(number) => (CANCEL, number),
+            SdsMessage::Wait(number) => (WAIT, number),
+            SdsMessage::Eof(number) => (EOF, number),
+        };
+        push(buf, &[NON_REALTIME, device_id.0, kind, number & 0x7F])
+    }
+}
+
+/// Send message as sysex packets, a message cut short by a transmit error is terminated
+fn transmit<P: Transmit>(port: &mut P, cable_number: CableNumber, device_id: U7, message: &SdsMessage) -> Result<(), MidiError> {
+    let mut body: Vec<u8, MAX_BODY_LEN> = Vec::new();
+    message.encode(device_id, &mut body)?;
+    transmit_sysex(port, &body, cable_number)
+}
+
+/// Read next SDS message addressed to device `device_id`, other packets are consumed and dropped
+fn receive<P: Receive, const N: usize>(
+    port: &mut P,
+    assembler: &mut SysexAssembler<N>,
+    device_id: U7,
+) -> Result<Option<(Packet, SdsMessage)>, MidiError> {
+    while let Some(packet) = port.receive()? {
+        // other sysex may be too long or interrupted, which is none of our business
+        let Ok(Some(body)) = assembler.advance(packet) else {
+            continue;
+        };
+        if let Ok((id, message)) = SdsMessage::decode(&body) {
+            if id == device_id {
+                return Ok(Some((packet, message)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdsStatus {
+    Busy,
+    Done,
+    Cancelled,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SenderState {
+    SendHeader,
+    AwaitHeaderAck,
+    SendPacket,
+    AwaitAck,
+    /// Receiver asked to wait, indefinitely, for its next handshake
+    Wait,
+    Done,
+    Cancelled,
+}
+
+/// Sends a sample dump, words are taken from `samples` as unsigned values of `header.bits` bits
+pub struct SdsSender<S> {
+    device_id: U7,
+    cable_number: CableNumber,
+    header: DumpHeader,
+    samples: S,
+    state: SenderState,
+    packet: DataPacket,
+    /// Words not yet packed
+    remaining: u32,
+    /// Time of last transmission, in ms
+    since: u32,
+    /// Handshakes are only 4 bytes long
+    assembler: SysexAssembler<4>,
+}
+
+impl<S: Iterator<Item = u32>> SdsSender<S> {
+    /// Returns Err(InvalidSysex) if sample words are not 8 to 28 bits
+    pub fn new(device_id: U7, header: DumpHeader, samples: S) -> Result<Self, MidiError> {
+        if !BITS.contains(&header.bits) {
+            return Err(MidiError::InvalidSysex);
+        }
+        let mut sender = SdsSender {
+            device_id,
+            cable_number: 0,
+            header,
+            samples,
+            state: SenderState::SendHeader,
+            packet: DataPacket::new(0, [0; PACKET_LEN]),
+            remaining: header.length,
+            since: 0,
+            assembler: SysexAssembler::new(),
+        };
+        sender.fill_packet();
+        Ok(sender)
+    }
+
+    pub fn with_cable(mut self, cable_number: CableNumber) -> Self {
+        self.cable_number = cable_number;
+        self
+    }
+
+    pub fn status(&self) -> SdsStatus {
+        match self.state {
+            SenderState::Done => SdsStatus::Done,
+            SenderState::Cancelled => SdsStatus::Cancelled,
+            _ => SdsStatus::Busy,
+        }
+    }
+
+    /// Pack next sample words into current packet, missing samples are zero
+    fn fill_packet(&mut self) {
+        let len = bytes_per_word(self.header.bits);
+        let count = self.remaining.min(words_per_packet(self.header.bits) as u32);
+        self.packet.data = [0; PACKET_LEN];
+        for word in self.packet.data.chunks_exact_mut(len).take(count as usize) {
+            pack_word(self.samples.next().unwrap_or(0), self.header.bits, word);
+        }
+        self.remaining -= count;
+    }
+
+    /// Move on to next packet once current one is acknowledged or timed out
+    fn next_packet(&mut self) {
+        if self.remaining == 0 {
+            self.state = SenderState::Done;
+        } else {
+            self.packet.number = (self.packet.number + 1) & 0x7F;
+            self.fill_packet();
+            self.state = SenderState::SendPacket;
+        }
+    }
+
+    /// Data packets follow the header, unless there is no data
+    fn first_packet(&mut self) {
+        self.state = if self.header.length == 0 { SenderState::Done } else { SenderState::SendPacket };
+    }
+
+    fn handshake(&mut self, message: SdsMessage) {
+        let number = self.packet.number;
+        match (self.state, message) {
+            (SenderState::Done | SenderState::Cancelled, _) => {}
+            (_, SdsMessage::Cancel(_)) => self.state = SenderState::Cancelled,
+            (SenderState::AwaitHeaderAck | SenderState::AwaitAck, SdsMessage::Wait(_)) => self.state = SenderState::Wait,
+            (SenderState::AwaitHeaderAck, SdsMessage::Ack(0)) => self.first_packet(),
+            (SenderState::AwaitHeaderAck, SdsMessage::Nak(_)) => self.state = SenderState::SendHeader,
+            (SenderState::AwaitAck | SenderState::Wait, SdsMessage::Ack(n)) if n == number => self.next_packet(),
+            (SenderState::AwaitAck | SenderState::Wait, SdsMessage::Nak(n)) if n == number => self.state = SenderState::SendPacket,
+            _ => {}
+        }
+    }
+
+    /// Abort transfer, notifying the receiver
+    pub fn cancel<P: Transmit>(&mut self, port: &mut P) -> Result<(), MidiError> {
+        self.state = SenderState::Cancelled;
+        transmit(port, self.cable_number, self.device_id, &SdsMessage::Cancel(self.packet.number))
+    }
+
+    /// Process received handshakes and send header or data packets when due, `now` is current time in ms
+    /// All packets received from `port` are consumed
+    /// returns:
+    /// - Ok(Busy) while transfer is in progress
+    /// - Ok(Done) once all data packets were acknowledged or timed out
+    /// - Ok(Cancelled) if receiver cancelled the transfer
+    pub fn poll<P: Receive + Transmit>(&mut self, port: &mut P, now: u32) -> Result<SdsStatus, MidiError> {
+        while let Some((_, message)) = receive(port, &mut self.assembler, self.device_id)? {
+            self.handshake(message);
+        }
+        let elapsed = now.wrapping_sub(self.since);
+        match self.state {
+            // receiver does not handshake, carry on open loop
+            SenderState::AwaitHeaderAck if elapsed > HEADER_TIMEOUT => self.first_packet(),
+            SenderState::AwaitAck if elapsed > PACKET_TIMEOUT => self.next_packet(),
+            _ => {}
+        }
+        if port.is_tx_full() {
+            return Ok(self.status());
+        }
+        let (message, next) = match self.state {
+            SenderState::SendHeader => (SdsMessage::DumpHeader(self.header), SenderState::AwaitHeaderAck),
+            SenderState::SendPacket => (SdsMessage::DataPacket(self.packet), SenderState::AwaitAck),
+            _ => return Ok(self.status()),
+        };
+        // on error, the message is sent again on next poll
+        transmit(port, self.cable_number, self.device_id, &message)?;
+        self.state = next;
+        self.since = now;
+        Ok(self.status())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdsEvent {
+    /// A new dump started, it was acknowledged
+    Header(DumpHeader),
+    /// Next sample words of the dump, in order
+    Samples(SampleWords),
+    Cancelled,
+    /// Sender stopped sending before the end of the dump
+    TimedOut,
+}
+
+/// Receives a sample dump, acknowledging valid packets and rejecting corrupted ones
+pub struct SdsReceiver {
+    device_id: U7,
+    cable_number: CableNumber,
+    header: Option<DumpHeader>,
+    expected: u8,
+    /// Words not yet received
+    remaining: u32,
+    /// Time of last data packet, in ms
+    since: u32,
+    timeout: u32,
+    /// Handshake that could not be transmitted, sent again on next poll
+    pending_reply: Option<SdsMessage>,
+    assembler: SysexAssembler<MAX_BODY_LEN>,
+}
+
+impl SdsReceiver {
+    pub fn new(device_id: U7) -> Self {
+        SdsReceiver {
+            device_id,
+            cable_number: 0,
+            header: None,
+            expected: 0,
+            remaining: 0,
+            since: 0,
+            timeout: 2000,
+            pending_reply: None,
+            assembler: SysexAssembler::new(),
+        }
+    }
+
+    /// Time in ms without data packets after which the dump is abandoned
+    pub fn with_timeout(mut self, timeout: u32) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Cable used for requests, replies go to the cable of the received message
+    pub fn with_cable(mut self, cable_number: CableNumber) -> Self {
+        self.cable_number = cable_number;
+        self
+    }
+
+    /// Header of dump being received
+    pub fn header(&self) -> Option<DumpHeader> {
+        self.header
+    }
+
+    /// Returns true from dump header until the last sample word is received
+    pub fn is_receiving(&self) -> bool {
+        self.header.is_some()
+    }
+
+    /// Ask the device to send sample `sample_number`
+    pub fn request<P: Transmit>(&mut self, port: &mut P, sample_number: U14) -> Result<(), MidiError> {
+        transmit(port, self.cable_number, self.device_id, &SdsMessage::DumpRequest(sample_number))
+    }
+
+    /// Ask the sender to hold on until the next handshake, e.g. while samples are written to storage
+    pub fn wait<P: Transmit>(&mut self, port: &mut P) -> Result<(), MidiError> {
+        transmit(port, self.cable_number, self.device_id, &SdsMessage::Wait(self.expected))
+    }
+
+    /// Abort dump being received, notifying the sender
+    pub fn cancel<P: Transmit>(&mut self, port: &mut P) -> Result<(), MidiError> {
+        self.header = None;
+        transmit(port, self.cable_number, self.device_id, &SdsMessage::Cancel(self.expected))
+    }
+
+    /// A handshake that fails to transmit replaces any pending one, it is sent again on next poll
+    fn reply<P: Transmit>(&mut self, port: &mut P, message: SdsMessage) {
+        let sent = transmit(port, self.cable_number, self.device_id, &message);
+        self.pending_reply = sent.err().map(|_| message);
+    }
+
+    /// Process received messages and answer them, `now` is current time in ms
+    /// All packets received from `port` are consumed
+    /// The dump is complete once `is_receiving()` turns false after a Samples event
+    /// Handshakes that could not be transmitted are sent again on next poll
+    /// returns:
+    /// - Ok(Some(event)) for each dump header, data packet with new samples, cancellation or timeout
+    /// - Ok(None) if nothing happened, headers of empty dumps are answered with CANCEL
+    pub fn poll<P: Receive + Transmit>(&mut self, port: &mut P, now: u32) -> Result<Option<SdsEvent>, MidiError> {
+        if let Some(message) = self.pending_reply {
+            self.reply(port, message);
+        }
+        while let Some((packet, message)) = receive(port, &mut self.assembler, self.device_id)? {
+            self.cable_number = packet.cable_number();
+            match (self.header, message) {
+                (_, SdsMessage::DumpHeader(header)) if header.length == 0 => {
+                    // no transfer would follow
+                    self.header = None;
+                    self.reply(port, SdsMessage::Cancel(0));
+                }
+                (_, SdsMessage::DumpHeader(header)) => {
+                    self.header = Some(header);
+                    self.expected = 0;
+                    self.remaining = header.length;
+                    self.since = now;
+                    self.reply(port, SdsMessage::Ack(0));
+                    return Ok(Some(SdsEvent::Header(header)));
+                }
+                (Some(header), SdsMessage::DataPacket(packet)) => {
+                    self.since = now;
+                    if !packet.valid {
+                        self.reply(port, SdsMessage::Nak(packet.number));
+                    } else if packet.number == self.expected {
+                        // samples are delivered even if the ACK has to wait for next poll
+                        let count = self.remaining.min(words_per_packet(header.bits) as u32);
+                        self.remaining -= count;
+                        self.expected = (self.expected + 1) & 0x7F;
+                        if self.remaining == 0 {
+                            self.header = None;
+                        }
+                        self.reply(port, SdsMessage::Ack(packet.number));
+                        return Ok(Some(SdsEvent::Samples(packet.words(header.bits, count as usize))));
+                    } else if packet.number == (self.expected + 0x7F) & 0x7F {
+                        // our ACK got lost and the packet was resent
+                        self.reply(port, SdsMessage::Ack(packet.number));
+                    } else {
+                        self.reply(port, SdsMessage::Nak(packet.number));
+                    }
+                }
+                (Some(_), SdsMessage::Cancel(_)) => {
+                    self.header = None;
+                    return Ok(Some(SdsEvent::Cancelled));
+                }
+                _ => {}
+            }
+        }
+        if self.header.is_some() && now.wrapping_sub(self.since) > self.timeout {
+            self.header = None;
+            return Ok(Some(SdsEvent::TimedOut));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: DumpHeader = DumpHeader {
+        sample_number: U14(3),
+        bits: 16,
+        period: 22676,
+        length: 100,
+        loop_start: 10,
+        loop_end: 90,
+        loop_type: LoopType::Forward,
+    };
+
+    #[derive(Default)]
+    struct Pipe {
+        rx: Vec<Packet, 64>,
+        tx: Vec<Packet, 64>,
+        /// Fail once when sending the packet at this index
+        fail_at: Option<usize>,
+    }
+
+    impl Receive for Pipe {
+        fn receive(&mut self) -> Result<Option<Packet>, MidiError> {
+            Ok((!self.rx.is_empty()).then(|| self.rx.remove(0)))
+        }
+    }
+
+    impl Transmit for Pipe {
+        fn is_tx_full(&self) -> bool {
+            self.tx.len() > self.tx.capacity() - PACKET_LEN / 3
+        }
+
+        fn transmit(&mut self, packet: Packet) -> Result<(), MidiError> {
+            if self.fail_at == Some(self.tx.len()) {
+                self.fail_at = None;
+                return Err(MidiError::BufferFull);
+            }
+            self.tx.push(packet).map_err(|_| MidiError::BufferFull)
+        }
+    }
+
+    /// Move transmitted packets to the other end
+    fn deliver(from: &mut Pipe, to: &mut Pipe) {
+        to.rx.extend(from.tx.iter().copied());
+        from.tx.clear();
+    }
+
+    #[test]
+    fn packing() {
+        let mut bytes = [0; 4];
+        pack_word(0x8000, 16, &mut bytes);
+        assert_eq!(&bytes[..3], &[0x40, 0x00, 0x00]);
+        for (word, bits) in [(0xAB, 8), (0x3FFF, 14), (0x1234, 16), (0x0ABC_DEF1, 28)] {
+            pack_word(word, bits, &mut bytes);
+            assert_eq!(unpack_word(&bytes, bits), word);
+        }
+        assert_eq!(words_per_packet(8), 60);
+        assert_eq!(words_per_packet(24), 30);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut body: Vec<u8, MAX_BODY_LEN> = Vec::new();
+        SdsMessage::DumpHeader(HEADER).encode(U7(1), &mut body).unwrap();
+        assert_eq!(body.len(), 19);
+        assert_eq!(SdsMessage::decode(&body).unwrap(), (U7(1), SdsMessage::DumpHeader(HEADER)));
+
+        let packet = DataPacket::new(127, [0x55; PACKET_LEN]);
+        body.clear();
+        SdsMessage::DataPacket(packet).encode(U7(1), &mut body).unwrap();
+        assert_eq!(body.len(), MAX_BODY_LEN);
+        assert_eq!(SdsMessage::decode(&body).unwrap(), (U7(1), SdsMessage::DataPacket(packet)));
+
+        body[10] ^= 1;
+        let Ok((_, SdsMessage::DataPacket(corrupted))) = SdsMessage::decode(&body) else { panic!() };
+        assert!(!corrupted.valid);
+    }
+
+    #[test]
+    fn transfer() {
+        let mut sender = SdsSender::new(U7(1), HEADER, 0..).unwrap().with_cable(2);
+        let mut receiver = SdsReceiver::new(U7(1));
+        let (mut sender_port, mut receiver_port) = (Pipe::default(), Pipe::default());
+        let mut words = 0;
+        let mut headers = 0;
+        for now in 0..10 {
+            assert_eq!(sender.poll(&mut sender_port, now).unwrap() == SdsStatus::Done, now > 3);
+            deliver(&mut sender_port, &mut receiver_port);
+            while let Some(event) = receiver.poll(&mut receiver_port, now).unwrap() {
+                match event {
+                    SdsEvent::Header(header) => headers += header.length,
+                    SdsEvent::Samples(samples) => {
+                        for word in samples {
+                            assert_eq!(word, words);
+                            words += 1;
+                        }
+                    }
+                    _ => panic!(),
+                }
+            }
+            deliver(&mut receiver_port, &mut sender_port);
+        }
+        assert_eq!((headers, words), (100, 100));
+        assert!(!receiver.is_receiving());
+    }
+
+    #[test]
+    fn open_loop() {
+        let mut sender = SdsSender::new(U7(1), HEADER, 0..).unwrap();
+        let mut port = Pipe::default();
+        assert_eq!(sender.poll(&mut port, 0).unwrap(), SdsStatus::Busy);
+        port.tx.clear();
+        assert_eq!(sender.poll(&mut port, 1000).unwrap(), SdsStatus::Busy);
+        assert!(port.tx.is_empty());
+        assert_eq!(sender.poll(&mut port, 2001).unwrap(), SdsStatus::Busy);
+        assert!(!port.tx.is_empty());
+
+        // NAK resends same packet, WAIT suspends timeouts, CANCEL aborts
+        let mut receiver = Pipe::default();
+        transmit(&mut receiver, 0, U7(1), &SdsMessage::Nak(0)).unwrap();
+        deliver(&mut receiver, &mut port);
+        port.tx.clear();
+        sender.poll(&mut port, 2002).unwrap();
+        let mut assembler = SysexAssembler::<MAX_BODY_LEN>::new();
+        let body = port.tx.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert!(matches!(SdsMessage::decode(&body), Ok((_, SdsMessage::DataPacket(DataPacket { number: 0, .. })))));
+
+        transmit(&mut receiver, 0, U7(1), &SdsMessage::Wait(0)).unwrap();
+        deliver(&mut receiver, &mut port);
+        port.tx.clear();
+        assert_eq!(sender.poll(&mut port, 5000).unwrap(), SdsStatus::Busy);
+        assert!(port.tx.is_empty());
+        transmit(&mut receiver, 0, U7(1), &SdsMessage::Cancel(0)).unwrap();
+        deliver(&mut receiver, &mut port);
+        assert_eq!(sender.poll(&mut port, 9000).unwrap(), SdsStatus::Cancelled);
+    }
+
+    #[test]
+    fn failed_transmit_is_retried() {
+        let mut sender = SdsSender::new(U7(1), HEADER, 0..).unwrap();
+        let mut port = Pipe::default();
+        sender.poll(&mut port, 0).unwrap();
+        port.tx.clear();
+        // room for a header but not for a data packet
+        while port.tx.len() < 24 {
+            port.tx.push(Packet::default()).unwrap();
+        }
+        assert!(sender.poll(&mut port, 2001).is_err());
+
+        port.tx.clear();
+        assert_eq!(sender.poll(&mut port, 2002).unwrap(), SdsStatus::Busy);
+        let mut assembler = SysexAssembler::<MAX_BODY_LEN>::new();
+        let body = port.tx.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert!(matches!(SdsMessage::decode(&body), Ok((_, SdsMessage::DataPacket(DataPacket { number: 0, .. })))));
+    }
+
+    #[test]
+    fn aborted_message_is_terminated() {
+        let mut port = Pipe { fail_at: Some(3), ..Pipe::default() };
+        let packet = SdsMessage::DataPacket(DataPacket::new(0, [0; PACKET_LEN]));
+        assert!(transmit(&mut port, 0, U7(1), &packet).is_err());
+        assert_eq!(port.tx.len(), 4);
+        assert_eq!(port.tx[3].payload(), &[0xF7]);
+        let mut assembler = SysexAssembler::<MAX_BODY_LEN>::new();
+        for packet in &port.tx {
+            assembler.advance(*packet).unwrap();
+        }
+        assert!(!assembler.is_receiving());
+    }
+
+    #[test]
+    fn packet_number_wraps() {
+        // 130 packets of 40 words
+        let header = DumpHeader { length: 5200, ..HEADER };
+        let mut sender = SdsSender::new(U7(1), header, 0..).unwrap();
+        let mut receiver = SdsReceiver::new(U7(1));
+        let (mut sender_port, mut receiver_port) = (Pipe::default(), Pipe::default());
+        let mut words = 0;
+        let mut now = 0;
+        while sender.poll(&mut sender_port, now).unwrap() == SdsStatus::Busy {
+            deliver(&mut sender_port, &mut receiver_port);
+            while let Some(event) = receiver.poll(&mut receiver_port, now).unwrap() {
+                if let SdsEvent::Samples(samples) = event {
+                    for word in samples {
+                        assert_eq!(word, words);
+                        words += 1;
+                    }
+                }
+            }
+            deliver(&mut receiver_port, &mut sender_port);
+            now += 1;
+        }
+        assert_eq!(words, 5200);
+        assert_eq!(sender.packet.number, 1);
+        assert!(!receiver.is_receiving());
+    }
+
+    #[test]
+    fn receiver() {
+        let mut receiver = SdsReceiver::new(U7(1)).with_timeout(100);
+        let mut port = Pipe::default();
+        let mut sender = Pipe::default();
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DumpHeader(HEADER)).unwrap();
+        deliver(&mut sender, &mut port);
+        // header is acknowledged
+        assert_eq!(receiver.poll(&mut port, 0).unwrap(), Some(SdsEvent::Header(HEADER)));
+
+        // out of sequence packet is rejected
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DataPacket(DataPacket::new(5, [0; PACKET_LEN]))).unwrap();
+        deliver(&mut sender, &mut port);
+        port.tx.clear();
+        assert_eq!(receiver.poll(&mut port, 10).unwrap(), None);
+        let mut assembler = SysexAssembler::<4>::new();
+        let body = port.tx.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert_eq!(SdsMessage::decode(&body).unwrap(), (U7(1), SdsMessage::Nak(5)));
+
+        assert_eq!(receiver.poll(&mut port, 200).unwrap(), Some(SdsEvent::TimedOut));
+        assert!(!receiver.is_receiving());
+    }
+
+    #[test]
+    fn header_handshake() {
+        let mut sender = SdsSender::new(U7(1), HEADER, 0..).unwrap();
+        let mut port = Pipe::default();
+        let mut receiver = Pipe::default();
+        sender.poll(&mut port, 0).unwrap();
+        // only ACK 0 acknowledges the header
+        transmit(&mut receiver, 0, U7(1), &SdsMessage::Ack(5)).unwrap();
+        deliver(&mut receiver, &mut port);
+        port.tx.clear();
+        sender.poll(&mut port, 1).unwrap();
+        assert!(port.tx.is_empty());
+        transmit(&mut receiver, 0, U7(1), &SdsMessage::Ack(0)).unwrap();
+        deliver(&mut receiver, &mut port);
+        sender.poll(&mut port, 2).unwrap();
+        assert!(!port.tx.is_empty());
+
+        // empty dump is cancelled
+        let mut receiver = SdsReceiver::new(U7(1));
+        let mut port = Pipe::default();
+        let mut sender = Pipe::default();
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DumpHeader(DumpHeader { length: 0, ..HEADER })).unwrap();
+        deliver(&mut sender, &mut port);
+        assert_eq!(receiver.poll(&mut port, 0).unwrap(), None);
+        assert!(!receiver.is_receiving());
+        let mut assembler = SysexAssembler::<4>::new();
+        let body = port.tx.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert_eq!(SdsMessage::decode(&body).unwrap(), (U7(1), SdsMessage::Cancel(0)));
+    }
+
+    #[test]
+    fn failed_ack_is_retried() {
+        let mut receiver = SdsReceiver::new(U7(1));
+        let mut port = Pipe::default();
+        let mut sender = Pipe::default();
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DumpHeader(HEADER)).unwrap();
+        deliver(&mut sender, &mut port);
+        receiver.poll(&mut port, 0).unwrap();
+        port.tx.clear();
+
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DataPacket(DataPacket::new(0, [0; PACKET_LEN]))).unwrap();
+        deliver(&mut sender, &mut port);
+        port.fail_at = Some(0);
+        let Some(SdsEvent::Samples(samples)) = receiver.poll(&mut port, 10).unwrap() else { panic!() };
+        assert_eq!(samples.count(), 40);
+        assert!(port.tx.is_empty());
+
+        assert_eq!(receiver.poll(&mut port, 20).unwrap(), None);
+        let mut assembler = SysexAssembler::<4>::new();
+        let body = port.tx.iter().find_map(|packet| assembler.advance(*packet).unwrap()).unwrap();
+        assert_eq!(SdsMessage::decode(&body).unwrap(), (U7(1), SdsMessage::Ack(0)));
+
+        // next packet is in sequence
+        port.tx.clear();
+        transmit(&mut sender, 0, U7(1), &SdsMessage::DataPacket(DataPacket::new(1, [0; PACKET_LEN]))).unwrap();
+        deliver(&mut sender, &mut port);
+        assert!(matches!(receiver.poll(&mut port, 30).unwrap(), Some(SdsEvent::Samples(_))));
+    }
+}
@@ -0,0 +1,740 @@
+//! Standard MIDI File (SMF) reader and writer, format 0 and 1
+//! Events are read on demand from any `SmfRead` source, without buffering whole tracks
+//! Sysex, text and other variable length data is referenced by its location in the file, see `Smf::read_data`
+
+use crate::status::{is_channel_status, is_non_status, SYSEX_END, SYSEX_START};
+use crate::{Message, MidiError, Status};
+use core::convert::TryFrom;
+use heapless::Vec;
+
+const HEADER_CHUNK: &[u8; 4] = b"MThd";
+const TRACK_CHUNK: &[u8; 4] = b"MTrk";
+
+/// Largest variable length quantity, 4 bytes of 7 bits
+const MAX_VARLEN: u32 = 0x0FFF_FFFF;
+
+const META: u8 = 0xFF;
+const META_TEXT_LAST: u8 = 0x0F;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const META_TIME_SIGNATURE: u8 = 0x58;
+const META_KEY_SIGNATURE: u8 = 0x59;
+
+/// Random access byte source, such as a file on an SD card
+pub trait SmfRead {
+    /// Read bytes starting at `offset` into `buf`, returns number of bytes read, 0 past end of source
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, MidiError>;
+}
+
+impl SmfRead for &[u8] {
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, MidiError> {
+        let start = (offset as usize).min(self.len());
+        let len = buf.len().min(self.len() - start);
+        buf[..len].copy_from_slice(&self[start..start + len]);
+        Ok(len)
+    }
+}
+
+fn read_exact<S: SmfRead>(source: &mut S, offset: u32, buf: &mut [u8]) -> Result<(), MidiError> {
+    if source.read_at(offset, buf)? < buf.len() {
+        return Err(MidiError::TruncatedMessage);
+    }
+    Ok(())
+}
+
+/// Timing unit of delta times
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Division {
+    TicksPerQuarter(u16),
+    /// Frames per second (24, 25, 29 for 30 drop-frame, 30) and ticks per frame
+    Timecode(u8, u8),
+}
+
+/// Err(InvalidFile) if frames per second is not one of the SMPTE rates
+impl TryFrom<u16> for Division {
+    type Error = MidiError;
+
+    fn try_from(raw: u16) -> Result<Self, Self::Error> {
+        if raw & 0x8000 == 0 {
+            return Ok(Division::TicksPerQuarter(raw));
+        }
+        // upper byte is the negated frame rate
+        match ((raw >> 8) as u8 as i8).checked_neg() {
+            Some(fps @ (24 | 25 | 29 | 30)) => Ok(Division::Timecode(fps as u8, raw as u8)),
+            _ => Err(MidiError::InvalidFile),
+        }
+    }
+}
+
+impl From<Division> for u16 {
+    fn from(division: Division) -> Self {
+        match division {
+            Division::TicksPerQuarter(ticks) => ticks & 0x7FFF,
+            Division::Timecode(fps, ticks) => (fps.wrapping_neg() as u16) << 8 | ticks as u16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmfHeader {
+    /// 0: single track, 1: simultaneous tracks, 2: independent patterns
+    pub format: u16,
+    pub tracks: u16,
+    pub division: Division,
+}
+
+/// Location of variable length event data in the file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmfData {
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl SmfData {
+    /// Data of this event in a file held in memory
+    pub fn get<'a>(&self, file: &'a [u8]) -> &'a [u8] {
+        let start = (self.offset as usize).min(file.len());
+        let end = start.saturating_add(self.len as usize).min(file.len());
+        &file[start..end]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SmfEvent {
+    Midi(Message),
+    /// Sysex body following SYSEX_START, usually ending with SYSEX_END
+    Sysex(SmfData),
+    /// Sysex continuation or raw bytes following SYSEX_END
+    Escape(SmfData),
+    /// Microseconds per quarter note
+    Tempo(u32),
+    /// Numerator, denominator as a power of 2, MIDI clocks per metronome click, 32nd notes per quarter note
+    TimeSignature(u8, u8, u8, u8),
+    /// Number of sharps (negative for flats), minor key
+    KeySignature(i8, bool),
+    /// Text meta event type (0x01-0x0F) and text
+    Text(u8, SmfData),
+    EndOfTrack,
+    /// Any other meta event type and its data
+    Meta(u8, SmfData),
+}
+
+/// Event with its absolute time in ticks since start of track
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimedEvent {
+    pub tick: u32,
+    /// Index of track the event belongs to
+    pub track: u16,
+    pub event: SmfEvent,
+}
+
+/// Bytes of a track read from the source at once
+const CURSOR_BUF_LEN: usize = 16;
+
+/// Read position and running state of a track
+#[derive(Copy, Clone, Debug)]
+struct TrackCursor {
+    index: u16,
+    pos: u32,
+    end: u32,
+    tick: u32,
+    running_status: Option<u8>,
+    ended: bool,
+    /// Track bytes starting at `buf_start`
+    buf: [u8; CURSOR_BUF_LEN],
+    buf_start: u32,
+    buf_len: u8,
+}
+
+impl TrackCursor {
+    fn new(index: u16, start: u32, end: u32) -> Self {
+        TrackCursor {
+            index,
+            pos: start,
+            end,
+            tick: 0,
+            running_status: None,
+            ended: false,
+            buf: [0; CURSOR_BUF_LEN],
+            buf_start: 0,
+            buf_len: 0,
+        }
+    }
+
+    fn read_byte<S: SmfRead>(&mut self, source: &mut S) -> Result<u8, MidiError> {
+        if self.pos >= self.end {
+            return Err(MidiError::TruncatedMessage);
+        }
+        let buffered = self.pos.wrapping_sub(self.buf_start);
+        if buffered >= self.buf_len as u32 {
+            // refill with the next bytes of the track
+            let len = (self.end - self.pos).min(CURSOR_BUF_LEN as u32) as usize;
+            let read = source.read_at(self.pos, &mut self.buf[..len])?;
+            if read == 0 {
+                return Err(MidiError::TruncatedMessage);
+            }
+            self.buf_start = self.pos;
+            self.buf_len = read as u8;
+        }
+        let byte = self.buf[(self.pos - self.buf_start) as usize];
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Variable length quantity, up to 4 bytes of 7 bits
+    fn read_varlen<S: SmfRead>(&mut self, source: &mut S) -> Result<u32, MidiError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.read_byte(source)?;
+            value = value << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MidiError::InvalidFile)
+    }
+
+    fn read_data<S: SmfRead>(&mut self, source: &mut S) -> Result<SmfData, MidiError> {
+        let len = self.read_varlen(source)?;
+        let data = SmfData { offset: self.pos, len };
+        if len > self.end - self.pos {
+            return Err(MidiError::TruncatedMessage);
+        }
+        self.pos += len;
+        Ok(data)
+    }
+
+    /// Channel message data bytes following status
+    fn read_midi<S: SmfRead>(&mut self, source: &mut S, status: u8) -> Result<SmfEvent, MidiError> {
+        let len = Status::try_from(status)?.expected_len() as usize;
+        let mut bytes = [status, 0, 0];
+        for byte in &mut bytes[1..len] {
+            *byte = self.read_byte(source)?;
+        }
+        Ok(SmfEvent::Midi(Message::try_from(&bytes[..len])?))
+    }
+
+    fn next<S: SmfRead>(&mut self, source: &mut S) -> Result<Option<TimedEvent>, MidiError> {
+        if self.ended || self.pos >= self.end {
+            return Ok(None);
+        }
+        self.tick = self.tick.wrapping_add(self.read_varlen(source)?);
+        let event = match self.read_byte(source)? {
+            META => {
+                self.running_status = None;
+                let kind = self.read_byte(source)?;
+                let data = self.read_data(source)?;
+                // fixed size meta events are read through the buffer
+                let mut bytes = [0; 4];
+                let next = self.pos;
+                self.pos = data.offset;
+                for byte in &mut bytes[..(data.len as usize).min(4)] {
+                    *byte = self.read_byte(source)?;
+                }
+                self.pos = next;
+                match (kind, data.len) {
+                    (META_END_OF_TRACK, _) => {
+                        self.ended = true;
+                        SmfEvent::EndOfTrack
+                    }
+                    (META_TEMPO, 3) => SmfEvent::Tempo(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])),
+                    (META_TIME_SIGNATURE, 4) => SmfEvent::TimeSignature(bytes[0], bytes[1], bytes[2], bytes[3]),
+                    (META_KEY_SIGNATURE, 2) => SmfEvent::KeySignature(bytes[0] as i8, bytes[1] != 0),
+                    (1..=META_TEXT_LAST, _) => SmfEvent::Text(kind, data),
+                    _ => SmfEvent::Meta(kind, data),
+                }
+            }
+            SYSEX_START => {
+                self.running_status = None;
+                SmfEvent::Sysex(self.read_data(source)?)
+            }
+            SYSEX_END => {
+                self.running_status = None;
+                SmfEvent::Escape(self.read_data(source)?)
+            }
+            status if is_channel_status(status) => {
+                self.running_status = Some(status);
+                self.read_midi(source, status)?
+            }
+            data if is_non_status(data) => {
+                let status = self.running_status.ok_or(MidiError::InvalidStatus(data))?;
+                self.pos -= 1;
+                self.read_midi(source, status)?
+            }
+            // system common and realtime messages can't appear in files
+            status => return Err(MidiError::InvalidStatus(status)),
+        };
+        Ok(Some(TimedEvent { tick: self.tick, track: self.index, event }))
+    }
+}
+
+/// Standard MIDI File reader
+#[derive(Debug)]
+pub struct Smf<S> {
+    source: S,
+    header: SmfHeader,
+    /// Offset of first chunk after the header
+    tracks_offset: u32,
+}
+
+impl<S: SmfRead> Smf<S> {
+    pub fn new(mut source: S) -> Result<Self, MidiError> {
+        let mut chunk = [0; 14];
+        read_exact(&mut source, 0, &mut chunk)?;
+        if &chunk[..4] != HEADER_CHUNK {
+            return Err(MidiError::InvalidFile);
+        }
+        let len = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        if len < 6 {
+            return Err(MidiError::InvalidFile);
+        }
+        let header = SmfHeader {
+            format: u16::from_be_bytes([chunk[8], chunk[9]]),
+            tracks: u16::from_be_bytes([chunk[10], chunk[11]]),
+            division: Division::try_from(u16::from_be_bytes([chunk[12], chunk[13]]))?,
+        };
+        let tracks_offset = len.checked_add(8).ok_or(MidiError::InvalidFile)?;
+        Ok(Smf { source, header, tracks_offset })
+    }
+
+    pub fn header(&self) -> SmfHeader {
+        self.header
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+
+    /// Read event data into `buf`, returns number of bytes read
+    pub fn read_data(&mut self, data: SmfData, buf: &mut [u8]) -> Result<usize, MidiError> {
+        let len = buf.len().min(data.len as usize);
+        self.source.read_at(data.offset, &mut buf[..len])
+    }
+
+    /// Locate a track chunk, skipping unknown chunks
+    fn cursor(&mut self, index: u16) -> Result<TrackCursor, MidiError> {
+        let mut offset = self.tracks_offset;
+        let mut found = 0;
+        loop {
+            let mut chunk = [0; 8];
+            read_exact(&mut self.source, offset, &mut chunk)?;
+            let len = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            let start = offset.checked_add(8).ok_or(MidiError::InvalidFile)?;
+            let end = start.checked_add(len).ok_or(MidiError::InvalidFile)?;
+            if &chunk[..4] == TRACK_CHUNK {
+                if found == index {
+                    return Ok(TrackCursor::new(index, start, end));
+                }
+                found += 1;
+            }
+            offset = end;
+        }
+    }
+
+    /// Events of a single track
+    pub fn track(&mut self, index: u16) -> Result<TrackEvents<'_, S>, MidiError> {
+        let cursor = self.cursor(index)?;
+        Ok(TrackEvents { source: &mut self.source, cursor })
+    }
+
+    /// Events of all tracks ordered by tick, events with the same tick are ordered by track
+    /// Err(BufferFull) if file has more than T tracks
+    pub fn merged<const T: usize>(&mut self) -> Result<MergedEvents<'_, S, T>, MidiError> {
+        let mut tracks = Vec::new();
+        for index in 0..self.header.tracks {
+            let mut cursor = self.cursor(index)?;
+            let next = cursor.next(&mut self.source)?;
+            tracks.push((cursor, next)).map_err(|_| MidiError::BufferFull)?;
+        }
+        Ok(MergedEvents { source: &mut self.source, tracks })
+    }
+}
+
+pub struct TrackEvents<'s, S> {
+    source: &'s mut S,
+    cursor: TrackCursor,
+}
+
+impl<'s, S: SmfRead> Iterator for TrackEvents<'s, S> {
+    type Item = Result<TimedEvent, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.cursor.next(self.source);
+        if next.is_err() {
+            // no point in reading further
+            self.cursor.ended = true;
+        }
+        next.transpose()
+    }
+}
+
+pub struct MergedEvents<'s, S, const T: usize> {
+    source: &'s mut S,
+    /// Cursor and next event of each track
+    tracks: Vec<(TrackCursor, Option<TimedEvent>), T>,
+}
+
+impl<'s, S: SmfRead, const T: usize> Iterator for MergedEvents<'s, S, T> {
+    type Item = Result<TimedEvent, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (cursor, next) = self.tracks.iter_mut()
+            .filter(|(_, next)| next.is_some())
+            .min_by_key(|(_, next)| next.map(|event| event.tick))?;
+        let event = next.take()?;
+        match cursor.next(self.source) {
+            Ok(following) => *next = following,
+            Err(err) => {
+                cursor.ended = true;
+                return Some(Err(err));
+            }
+        }
+        Some(Ok(event))
+    }
+}
+
+/// Random access byte sink, written mostly sequentially
+pub trait SmfWrite {
+    /// Write bytes starting at `offset`, which is never past the end of previously written bytes
+    fn write_at(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MidiError>;
+}
+
+impl SmfWrite for &mut [u8] {
+    fn write_at(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MidiError> {
+        let start = offset as usize;
+        self.get_mut(start..start + bytes.len()).ok_or(MidiError::BufferFull)?.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl<const N: usize> SmfWrite for Vec<u8, N> {
+    fn write_at(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MidiError> {
+        let start = offset as usize;
+        let overlap = bytes.len().min(self.len().saturating_sub(start));
+        self[start..start + overlap].copy_from_slice(&bytes[..overlap]);
+        self.extend_from_slice(&bytes[overlap..]).map_err(|_| MidiError::BufferFull)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TrackState {
+    /// Offset of the track's length field
+    len_offset: u32,
+    tick: u32,
+    running_status: Option<u8>,
+}
+
+/// Standard MIDI File writer
+/// Tracks are written one after the other, events of a track must be written in tick order
+#[derive(Debug)]
+pub struct SmfWriter<W> {
+    sink: W,
+    header: SmfHeader,
+    running_status: bool,
+    pos: u32,
+    track: Option<TrackState>,
+}
+
+impl<W: SmfWrite> SmfWriter<W> {
+    pub fn new(sink: W, format: u16, division: Division) -> Self {
+        SmfWriter {
+            sink,
+            header: SmfHeader { format, tracks: 0, division },
+            running_status: false,
+            pos: 0,
+            track: None,
+        }
+    }
+
+    /// Omit repeated status bytes of channel messages
+    pub fn with_running_status(mut self) -> Self {
+        self.running_status = true;
+        self
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), MidiError> {
+        self.sink.write_at(self.pos, bytes)?;
+        self.pos += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), MidiError> {
+        let mut chunk = [0; 14];
+        chunk[..4].copy_from_slice(HEADER_CHUNK);
+        chunk[4..8].copy_from_slice(&6u32.to_be_bytes());
+        chunk[8..10].copy_from_slice(&self.header.format.to_be_bytes());
+        chunk[10..12].copy_from_slice(&self.header.tracks.to_be_bytes());
+        chunk[12..14].copy_from_slice(&u16::from(self.header.division).to_be_bytes());
+        self.sink.write_at(0, &chunk)
+    }
+
+    /// Err(InvalidFile) if value does not fit in 4 bytes of 7 bits
+    fn write_varlen(&mut self, value: u32) -> Result<(), MidiError> {
+        if value > MAX_VARLEN {
+            return Err(MidiError::InvalidFile);
+        }
+        let mut bytes = [0; 4];
+        let mut len = 1;
+        bytes[3] = value as u8 & 0x7F;
+        while len < 4 && value >> (7 * len) != 0 {
+            bytes[3 - len] = (value >> (7 * len)) as u8 | 0x80;
+            len += 1;
+        }
+        self.write(&bytes[4 - len..])
+    }
+
+    /// Write delta time of event at absolute `tick`, ticks before the previous event are written as 0
+    /// Err(InvalidFile) if the delta time exceeds 0x0FFFFFFF ticks
+    fn write_delta(&mut self, tick: u32) -> Result<TrackState, MidiError> {
+        let mut track = self.track.ok_or(MidiError::InvalidFile)?;
+        self.write_varlen(tick.saturating_sub(track.tick))?;
+        track.tick = tick.max(track.tick);
+        Ok(track)
+    }
+
+    /// Start a new track, ending the current one at its last event
+    /// Err(InvalidFile) if a format 0 file already has a track
+    pub fn begin_track(&mut self) -> Result<(), MidiError> {
+        if let Some(track) = self.track {
+            self.end_track(track.tick)?;
+        }
+        if self.header.format == 0 && self.header.tracks > 0 {
+            return Err(MidiError::InvalidFile);
+        }
+        if self.pos == 0 {
+            self.write_header()?;
+            self.pos = 14;
+        }
+        self.write(TRACK_CHUNK)?;
+        self.track = Some(TrackState { len_offset: self.pos, tick: 0, running_status: None });
+        self.write(&[0; 4])?;
+        self.header.tracks += 1;
+        Ok(())
+    }
+
+    /// Write End of Track meta event and patch the track's length
+    pub fn end_track(&mut self, tick: u32) -> Result<(), MidiError> {
+        self.write_delta(tick)?;
+        self.write(&[META, META_END_OF_TRACK, 0])?;
+        let track = self.track.take().ok_or(MidiError::InvalidFile)?;
+        let len = self.pos - track.len_offset - 4;
+        self.sink.write_at(track.len_offset, &len.to_be_bytes())
+    }
+
+    /// Write a channel message at absolute `tick`
+    /// Err(InvalidStatus) for system messages, which can't appear in files
+    pub fn message(&mut self, tick: u32, message: Message) -> Result<(), MidiError> {
+        let mut bytes = [0; 3];
+        let len = message.encode_into(&mut bytes);
+        if len == 0 || !is_channel_status(bytes[0]) {
+            return Err(MidiError::InvalidStatus(bytes[0]));
+        }
+        let mut track = self.write_delta(tick)?;
+        let skip = self.running_status && track.running_status == Some(bytes[0]);
+        self.write(&bytes[skip as usize..len])?;
+        track.running_status = Some(bytes[0]);
+        self.track = Some(track);
+        Ok(())
+    }
+
+    /// Write a sysex event, body excluding SYSEX_START and SYSEX_END
+    pub fn sysex(&mut self, tick: u32, body: &[u8]) -> Result<(), MidiError> {
+        let mut track = self.write_delta(tick)?;
+        self.write(&[SYSEX_START])?;
+        self.write_varlen(body.len() as u32 + 1)?;
+        self.write(body)?;
+        self.write(&[SYSEX_END])?;
+        track.running_status = None;
+        self.track = Some(track);
+        Ok(())
+    }
+
+    /// Write a meta event of any type
+    pub fn meta(&mut self, tick: u32, kind: u8, data: &[u8]) -> Result<(), MidiError> {
+        let mut track = self.write_delta(tick)?;
+        self.write(&[META, kind])?;
+        self.write_varlen(data.len() as u32)?;
+        self.write(data)?;
+        track.running_status = None;
+        self.track = Some(track);
+        Ok(())
+    }
+
+    /// Microseconds per quarter note
+    pub fn tempo(&mut self, tick: u32, tempo: u32) -> Result<(), MidiError> {
+        self.meta(tick, META_TEMPO, &tempo.to_be_bytes()[1..])
+    }
+
+    /// Numerator, denominator as a power of 2, MIDI clocks per metronome click, 32nd notes per quarter note
+    pub fn time_signature(&mut self, tick: u32, numerator: u8, denominator: u8, clocks: u8, notated_32nds: u8) -> Result<(), MidiError> {
+        self.meta(tick, META_TIME_SIGNATURE, &[numerator, denominator, clocks, notated_32nds])
+    }
+
+    /// Number of sharps (negative for flats), minor key
+    pub fn key_signature(&mut self, tick: u32, sharps: i8, minor: bool) -> Result<(), MidiError> {
+        self.meta(tick, META_KEY_SIGNATURE, &[sharps as u8, minor as u8])
+    }
+
+    /// Text meta event type (0x01-0x0F)
+    pub fn text(&mut self, tick: u32, kind: u8, text: &[u8]) -> Result<(), MidiError> {
+        self.meta(tick, kind.clamp(1, META_TEXT_LAST), text)
+    }
+
+    /// End the current track and patch track count
+    /// Returns the sink and the file length
+    pub fn finish(mut self) -> Result<(W, u32), MidiError> {
+        if let Some(track) = self.track {
+            self.end_track(track.tick)?;
+        }
+        if self.pos == 0 {
+            self.pos = 14;
+        }
+        self.write_header()?;
+        Ok((self.sink, self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Channel, Note, U7};
+
+    const FILE: &[u8] = &[
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 96,
+        // tempo track
+        b'M', b'T', b'r', b'k', 0, 0, 0, 26,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x58, 0x04, 0x04, 0x02, 0x18, 0x08,
+        0x00, 0xFF, 0x03, 0x02, b'h', b'i',
+        0x81, 0x00, 0xFF, 0x2F, 0x00,
+        // notes, with running status
+        b'M', b'T', b'r', b'k', 0, 0, 0, 21,
+        0x00, 0x90, 0x3C, 0x64,
+        0x60, 0x3C, 0x00,
+        0x10, 0xF0, 0x03, 0x7D, 0x01, 0xF7,
+        0x10, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+
+    #[test]
+    fn header() {
+        let smf = Smf::new(FILE).unwrap();
+        assert_eq!(smf.header(), SmfHeader { format: 1, tracks: 2, division: Division::TicksPerQuarter(96) });
+        assert_eq!(Division::try_from(u16::from(Division::Timecode(25, 40))).unwrap(), Division::Timecode(25, 40));
+        assert_eq!(Division::try_from(0xE228).unwrap(), Division::Timecode(30, 40));
+        assert!(matches!(Division::try_from(0x8028), Err(MidiError::InvalidFile)));
+        assert!(matches!(Division::try_from(0xE928), Err(MidiError::InvalidFile)));
+
+        let mut file = [0; 14];
+        file.copy_from_slice(&FILE[..14]);
+        file[12] = 0x80;
+        assert!(matches!(Smf::new(&file[..]), Err(MidiError::InvalidFile)));
+    }
+
+    #[test]
+    fn oversized_chunk() {
+        let mut file = [0; 22];
+        file[..14].copy_from_slice(&FILE[..14]);
+        file[14..].copy_from_slice(b"XXXX\xFF\xFF\xFF\xF8");
+        let mut smf = Smf::new(&file[..]).unwrap();
+        assert!(matches!(smf.track(0), Err(MidiError::InvalidFile)));
+
+        file[4..8].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xF8]);
+        assert!(matches!(Smf::new(&file[..]), Err(MidiError::InvalidFile)));
+    }
+
+    #[test]
+    fn track() {
+        let mut smf = Smf::new(FILE).unwrap();
+        let events: Vec<_, 8> = smf.track(0).unwrap().map(Result::unwrap).collect();
+        assert!(matches!(events[0].event, SmfEvent::Tempo(500_000)));
+        assert!(matches!(events[1].event, SmfEvent::TimeSignature(4, 2, 24, 8)));
+        let SmfEvent::Text(3, text) = events[2].event else { panic!() };
+        assert_eq!(text.get(FILE), b"hi");
+        assert_eq!(events[3].tick, 128);
+        assert!(matches!(events[3].event, SmfEvent::EndOfTrack));
+    }
+
+    /// Counts reads from the source
+    struct Counting<'a> {
+        file: &'a [u8],
+        reads: u32,
+    }
+
+    impl SmfRead for Counting<'_> {
+        fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, MidiError> {
+            self.reads += 1;
+            self.file.read_at(offset, buf)
+        }
+    }
+
+    #[test]
+    fn buffered_reads() {
+        let mut smf = Smf::new(Counting { file: FILE, reads: 0 }).unwrap();
+        let events: Vec<_, 8> = smf.track(1).unwrap().map(Result::unwrap).collect();
+        assert_eq!(events.len(), 5);
+        // header, two chunk headers and two buffer fills for 21 bytes of events
+        assert_eq!(smf.into_inner().reads, 5);
+    }
+
+    #[test]
+    fn merged() {
+        let mut smf = Smf::new(FILE).unwrap();
+        let events: Vec<_, 16> = smf.merged::<2>().unwrap().map(Result::unwrap).collect();
+        let ticks: Vec<_, 16> = events.iter().map(|e| (e.tick, e.track)).collect();
+        assert_eq!(ticks.as_slice(), &[(0, 0), (0, 0), (0, 0), (0, 1), (96, 1), (112, 1), (128, 0), (128, 1), (128, 1)]);
+        assert!(matches!(events[4].event, SmfEvent::Midi(Message::NoteOn(_, Note::C4, U7(0)))));
+        let SmfEvent::Sysex(sysex) = events[5].event else { panic!() };
+        assert_eq!(sysex.get(FILE), &[0x7D, 0x01, 0xF7]);
+        assert!(smf.merged::<1>().is_err());
+    }
+
+    #[test]
+    fn write() {
+        let mut writer = SmfWriter::new(Vec::<u8, 128>::new(), 1, Division::TicksPerQuarter(96)).with_running_status();
+        writer.begin_track().unwrap();
+        writer.tempo(0, 500_000).unwrap();
+        writer.time_signature(0, 4, 2, 24, 8).unwrap();
+        writer.text(0, 3, b"hi").unwrap();
+        writer.end_track(128).unwrap();
+        writer.begin_track().unwrap();
+        writer.message(0, Message::NoteOn(Channel(0), Note::C4, U7(100))).unwrap();
+        writer.message(96, Message::NoteOn(Channel(0), Note::C4, U7(0))).unwrap();
+        writer.sysex(112, &[0x7D, 0x01]).unwrap();
+        writer.message(128, Message::NoteOff(Channel(0), Note::C4, U7(64))).unwrap();
+        assert!(writer.message(128, Message::TimingClock).is_err());
+        let (file, len) = writer.finish().unwrap();
+        assert_eq!(len as usize, FILE.len());
+        assert_eq!(file.as_slice(), FILE);
+
+        // format 0 has a single track
+        let mut buf = [0; 32];
+        let mut writer = SmfWriter::new(&mut buf[..], 0, Division::TicksPerQuarter(96));
+        writer.begin_track().unwrap();
+        assert!(matches!(writer.begin_track(), Err(MidiError::InvalidFile)));
+
+        let mut buf = [0; 16];
+        let mut writer = SmfWriter::new(&mut buf[..], 0, Division::TicksPerQuarter(96));
+        assert!(matches!(writer.begin_track(), Err(MidiError::BufferFull)));
+    }
+
+    #[test]
+    fn long_delta() {
+        let mut writer = SmfWriter::new(Vec::<u8, 64>::new(), 0, Division::TicksPerQuarter(96));
+        writer.begin_track().unwrap();
+        let note = Message::NoteOn(Channel(0), Note::C4, U7(100));
+        writer.message(MAX_VARLEN, note).unwrap();
+        assert!(matches!(writer.message(2 * MAX_VARLEN + 1, note), Err(MidiError::InvalidFile)));
+        writer.message(2 * MAX_VARLEN, note).unwrap();
+        let (file, _) = writer.finish().unwrap();
+
+        let mut smf = Smf::new(file.as_slice()).unwrap();
+        let ticks: Vec<_, 4> = smf.track(0).unwrap().map(|event| event.unwrap().tick).collect();
+        assert_eq!(ticks.as_slice(), &[MAX_VARLEN, 2 * MAX_VARLEN, 2 * MAX_VARLEN]);
+    }
+}
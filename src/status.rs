@@ -38,6 +38,12 @@ pub fn is_channel_status(byte: u8) -> bool {
     (NOTE_OFF..SYSEX_START).contains(&byte)
 }
 
+/// System Realtime bytes can appear anywhere, even between bytes of another message
+/// Measure End (0xF9) carries a data byte and is handled as System Common instead
+pub fn is_realtime(byte: u8) -> bool {
+    byte >= TIMING_CLOCK && byte != MEASURE_END
+}
+
 #[derive(Copy, Clone, Debug, UnsafeFromPrimitive, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Status {
@@ -77,6 +83,7 @@ pub fn status_byte(msg: &Message) -> Option<u8> {
         Message::ChannelPressure(ch, ..) => Some(Status::ChannelPressure as u8 + ch.0),
         Message::ProgramChange(ch, ..) => Some(Status::ProgramChange as u8 + ch.0),
         Message::ControlChange(ch, ..) => Some(Status::ControlChange as u8 + ch.0),
+        Message::ChannelMode(ch, ..) => Some(Status::ControlChange as u8 + ch.0),
         Message::PitchBend(ch, ..) => Some(Status::PitchBend as u8 + ch.0),
 
         Message::TimeCodeQuarterFrame(_) => Some(Status::TimeCodeQuarterFrame as u8),
@@ -113,7 +120,8 @@ impl TryFrom<u8> for Status {
     type Error = MidiError;
 
     fn try_from(mut byte: u8) -> Result<Self, Self::Error> {
-        if is_non_status(byte) {
+        // 0xF4, 0xF5 and 0xFD are undefined
+        if is_non_status(byte) || matches!(byte, 0xF4 | 0xF5 | 0xFD) {
             return Err(MidiError::InvalidStatus(byte));
         }
         if is_channel_status(byte) {
@@ -0,0 +1,261 @@
+//! Sysex messages spread over multiple USB-MIDI Event Packets
+
+use crate::status::{is_realtime, SYSEX_END, SYSEX_START};
+use crate::{CableNumber, CodeIndexNumber, Message, MidiError, Packet, Transmit};
+use core::mem;
+use heapless::Vec;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+enum AssemblerState {
+    #[default]
+    Idle,
+    Receiving,
+    /// Body exceeded capacity, skip until SYSEX_END
+    Discarding,
+}
+
+/// Collects the sysex packets of a stream into complete sysex bodies of up to N bytes
+#[derive(Debug, Default)]
+pub struct SysexAssembler<const N: usize> {
+    state: AssemblerState,
+    body: Vec<u8, N>,
+}
+
+impl<const N: usize> SysexAssembler<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if a sysex was started but not yet terminated
+    pub fn is_receiving(&self) -> bool {
+        self.state != AssemblerState::Idle
+    }
+
+    /// Drop any partially received sysex
+    pub fn reset(&mut self) {
+        self.state = AssemblerState::Idle;
+        self.body.clear();
+    }
+
+    /// Push next packet
+    /// returns:
+    /// - Ok(None) if packet is not sysex or sysex is incomplete
+    /// - Ok(Some(body)) if sysex is complete, body _excludes_ SYSEX_START and SYSEX_END markers
+    /// - Err(SysexOutOfBounds) if body exceeds N bytes, remaining packets of that sysex are skipped
+    /// - Err(SysexInterrupted) if a non-realtime message or another sysex arrived before SYSEX_END
+    pub fn advance(&mut self, packet: Packet) -> Result<Option<Vec<u8, N>>, MidiError> {
+        let payload = packet.payload();
+        let Some(&first) = payload.first() else {
+            return Ok(None);
+        };
+
+        let ends = match packet.code_index_number() {
+            CodeIndexNumber::Sysex => false,
+            CodeIndexNumber::SysexEndsNext2 | CodeIndexNumber::SysexEndsNext3 => true,
+            CodeIndexNumber::SystemCommonLen1 if first == SYSEX_END => true,
+            _ => {
+                // realtime messages may be interleaved with sysex, anything else terminates it
+                if self.is_receiving() && !is_realtime(first) {
+                    self.reset();
+                    return Err(MidiError::SysexInterrupted);
+                }
+                return Ok(None);
+            }
+        };
+
+        let mut interrupted = false;
+        if first == SYSEX_START {
+            interrupted = self.is_receiving();
+            self.reset();
+            self.state = AssemblerState::Receiving;
+        } else if !self.is_receiving() {
+            // continuation of a sysex we never saw starting
+            return Ok(None);
+        }
+
+        let mut overflow = false;
+        if self.state == AssemblerState::Receiving && self.body.extend_from_slice(packet.sysex_body()).is_err() {
+            self.state = AssemblerState::Discarding;
+            overflow = true;
+        }
+
+        let mut complete = None;
+        if ends {
+            if mem::take(&mut self.state) == AssemblerState::Receiving {
+                complete = Some(mem::take(&mut self.body));
+            }
+            self.body.clear();
+        }
+
+        if interrupted {
+            Err(MidiError::SysexInterrupted)
+        } else if overflow {
+            Err(MidiError::SysexOutOfBounds)
+        } else {
+            Ok(complete)
+        }
+    }
+}
+
+/// Splits a sysex body into USB-MIDI Event Packets
+/// SYSEX_START and SYSEX_END markers are added if the body does not already include them
+#[derive(Debug, Clone)]
+pub struct SysexPackets<'a> {
+    body: &'a [u8],
+    cable_number: CableNumber,
+    pos: usize,
+}
+
+impl<'a> SysexPackets<'a> {
+    pub fn new(body: &'a [u8], cable_number: CableNumber) -> Self {
+        let body = body.strip_prefix(&[SYSEX_START]).unwrap_or(body);
+        let body = body.strip_suffix(&[SYSEX_END]).unwrap_or(body);
+        SysexPackets { body, cable_number, pos: 0 }
+    }
+
+    /// Body length including SYSEX_START and SYSEX_END
+    fn total_len(&self) -> usize {
+        self.body.len() + 2
+    }
+
+    fn byte_at(&self, pos: usize) -> u8 {
+        match pos {
+            0 => SYSEX_START,
+            pos if pos > self.body.len() => SYSEX_END,
+            pos => self.body[pos - 1],
+        }
+    }
+}
+
+impl<'a> Iterator for SysexPackets<'a> {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        let remaining = self.total_len().saturating_sub(self.pos);
+        if remaining == 0 {
+            return None;
+        }
+        let len = remaining.min(3);
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes[1..=len].iter_mut().enumerate() {
+            *byte = self.byte_at(self.pos + i);
+        }
+        self.pos += len;
+        let cin = if len == remaining {
+            CodeIndexNumber::end_sysex(len as u8).unwrap_or(CodeIndexNumber::Sysex)
+        } else {
+            CodeIndexNumber::Sysex
+        };
+        bytes[0] = cin as u8;
+        Some(Packet::from_raw(bytes).with_cable_num(self.cable_number))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.total_len().saturating_sub(self.pos).div_ceil(3);
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for SysexPackets<'a> {}
+
+/// Send a sysex body as packets
+/// If a packet can't be sent after the sysex started, a SYSEX_END packet is sent to terminate it,
+/// so that messages sent afterwards are not taken as part of the sysex
+pub fn transmit_sysex<P: Transmit>(port: &mut P, body: &[u8], cable_number: CableNumber) -> Result<(), MidiError> {
+    for (index, packet) in SysexPackets::new(body, cable_number).enumerate() {
+        if let Err(err) = port.transmit(packet) {
+            if index > 0 {
+                let _ = port.transmit(Packet::from(Message::SysexEnd).with_cable_num(cable_number));
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, PacketList};
+    use core::convert::TryFrom;
+
+    fn feed<const N: usize>(assembler: &mut SysexAssembler<N>, messages: &[Message]) -> Result<Option<Vec<u8, N>>, MidiError> {
+        let mut last = Ok(None);
+        for msg in messages {
+            last = assembler.advance(Packet::from(*msg));
+        }
+        last
+    }
+
+    #[test]
+    fn assembles_body() {
+        let mut assembler = SysexAssembler::<16>::new();
+        let body = feed(&mut assembler, &[
+            Message::SysexBegin(1, 2),
+            Message::TimingClock,
+            Message::SysexCont(3, 4, 5),
+            Message::SysexEnd1(6),
+        ]);
+        assert_eq!(&body.unwrap().unwrap()[..], &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn special_cases() {
+        let mut assembler = SysexAssembler::<16>::new();
+        assert_eq!(&feed(&mut assembler, &[Message::SysexEmpty]).unwrap().unwrap()[..], &[]);
+        assert_eq!(&feed(&mut assembler, &[Message::SysexSingleByte(7)]).unwrap().unwrap()[..], &[7]);
+        assert_eq!(&feed(&mut assembler, &[Message::SysexBegin(1, 2), Message::SysexEnd]).unwrap().unwrap()[..], &[1, 2]);
+    }
+
+    #[test]
+    fn overflow_skips_to_end() {
+        let mut assembler = SysexAssembler::<4>::new();
+        assert!(feed(&mut assembler, &[Message::SysexBegin(1, 2)]).is_ok());
+        assert!(matches!(assembler.advance(Packet::from(Message::SysexCont(3, 4, 5))), Err(MidiError::SysexOutOfBounds)));
+        assert!(matches!(assembler.advance(Packet::from(Message::SysexEnd2(6, 7))), Ok(None)));
+        assert!(!assembler.is_receiving());
+    }
+
+    #[test]
+    fn fragments_special_cases() {
+        let empty: PacketList = SysexPackets::new(&[], 0).collect();
+        assert_eq!(empty.len(), 1);
+        assert!(matches!(Message::try_from(empty[0]), Ok(Message::SysexEmpty)));
+
+        let single: PacketList = SysexPackets::new(&[0xF0, 5, 0xF7], 0).collect();
+        assert_eq!(single.len(), 1);
+        assert!(matches!(Message::try_from(single[0]), Ok(Message::SysexSingleByte(5))));
+    }
+
+    #[test]
+    fn fragments_tail_cin() {
+        for (len, tail) in [(1, CodeIndexNumber::SysexEndsNext3), (2, CodeIndexNumber::SystemCommonLen1), (3, CodeIndexNumber::SysexEndsNext2), (4, CodeIndexNumber::SysexEndsNext3)] {
+            let body = [1, 2, 3, 4];
+            let packets = SysexPackets::new(&body[..len], 3);
+            assert_eq!(packets.len(), (len + 4) / 3);
+            let packets: PacketList = packets.collect();
+            assert_eq!(packets.last().unwrap().code_index_number(), tail);
+            assert!(packets.iter().all(|p| p.cable_number() == 3));
+        }
+    }
+
+    #[test]
+    fn fragments_round_trip() {
+        let body = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut assembler = SysexAssembler::<16>::new();
+        let mut result = None;
+        for packet in SysexPackets::new(&body, 0) {
+            result = assembler.advance(packet).unwrap();
+        }
+        assert_eq!(&result.unwrap()[..], &body);
+    }
+
+    #[test]
+    fn interrupted_by_channel_message() {
+        let mut assembler = SysexAssembler::<16>::new();
+        assert!(feed(&mut assembler, &[Message::SysexBegin(1, 2)]).is_ok());
+        let note = crate::note_on(crate::channel(1), crate::Note::C4, 64).unwrap();
+        assert!(matches!(assembler.advance(Packet::from(note)), Err(MidiError::SysexInterrupted)));
+        assert!(!assembler.is_receiving());
+    }
+}
@@ -0,0 +1,331 @@
+//! Default translation between MIDI 1.0 messages and MIDI 2.0 Channel Voice messages
+//! Values are upscaled using the spec's min-center-max scheme and downscaled by truncation
+
+use crate::ump::{Midi2Message, NoteAttribute, Sysex7, SysexStatus};
+use crate::status::{SYSEX_END, SYSEX_START};
+use crate::{Channel, ChannelMode, Control, Cull, Message, MidiError, ParameterChange, ParameterDecoder, ParameterKind, U14, U7};
+use core::convert::TryFrom;
+use heapless::Vec;
+
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+
+/// MIDI 1.0 Note Off velocity used for Note On with zero velocity
+const NOTE_OFF_VELOCITY: u8 = 64;
+
+/// Scale a value of `src_bits` up to `dst_bits`, preserving minimum, center and maximum values
+/// Value is returned unchanged unless `0 < src_bits < dst_bits <= 32`
+pub fn scale_up(value: u32, src_bits: u8, dst_bits: u8) -> u32 {
+    if src_bits == 0 || src_bits >= dst_bits || dst_bits > 32 {
+        return value;
+    }
+    let scale_bits = dst_bits - src_bits;
+    let mut scaled = value << scale_bits;
+    if value <= 1 << (src_bits - 1) {
+        return scaled;
+    }
+    // repeat lower bits of value to fill lower bits of scaled value
+    let repeat_bits = src_bits - 1;
+    let mut repeat = value & ((1 << repeat_bits) - 1);
+    if scale_bits > repeat_bits {
+        repeat <<= scale_bits - repeat_bits;
+    } else {
+        repeat >>= repeat_bits - scale_bits;
+    }
+    while repeat != 0 {
+        scaled |= repeat;
+        repeat >>= repeat_bits;
+    }
+    scaled
+}
+
+/// Scale a value of `src_bits` down to `dst_bits`
+/// Value is returned unchanged if `dst_bits` is not below `src_bits`
+pub fn scale_down(value: u32, src_bits: u8, dst_bits: u8) -> u32 {
+    value.checked_shr(src_bits.saturating_sub(dst_bits) as u32).unwrap_or(0)
+}
+
+/// Translates MIDI 1.0 Channel Voice messages to MIDI 2.0
+/// RPN/NRPN and Bank Select Control Changes are tracked and merged into the messages they apply to
+#[derive(Debug, Default)]
+pub struct Midi1Translator {
+    parameters: ParameterDecoder,
+    /// Bank MSB & LSB, if selected
+    banks: [Option<(U7, U7)>; 16],
+}
+
+impl Midi1Translator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget parameter and bank selection on all channels
+    pub fn reset(&mut self) {
+        self.parameters.reset();
+        self.banks = Default::default();
+    }
+
+    /// Push next message
+    /// returns:
+    /// - None if message has no MIDI 2.0 Channel Voice equivalent or only updates translator state
+    /// - Some(message) otherwise
+    pub fn advance(&mut self, message: Message) -> Option<Midi2Message> {
+        let up7 = |value: U7| scale_up(value.0 as u32, 7, 32);
+        Some(match message {
+            Message::NoteOff(ch, note, velocity) => Midi2Message::NoteOff(ch, note, scale_up(velocity.0 as u32, 7, 16) as u16, NoteAttribute::default()),
+            Message::NoteOn(ch, note, U7(0)) => Midi2Message::NoteOff(ch, note, scale_up(NOTE_OFF_VELOCITY as u32, 7, 16) as u16, NoteAttribute::default()),
+            Message::NoteOn(ch, note, velocity) => Midi2Message::NoteOn(ch, note, scale_up(velocity.0 as u32, 7, 16) as u16, NoteAttribute::default()),
+            Message::NotePressure(ch, note, pressure) => Midi2Message::NotePressure(ch, note, up7(pressure)),
+            Message::ChannelPressure(ch, pressure) => Midi2Message::ChannelPressure(ch, up7(pressure)),
+            Message::PitchBend(ch, bend) => Midi2Message::PitchBend(ch, scale_up(bend.0 as u32, 14, 32)),
+            Message::ProgramChange(ch, program) => {
+                let bank = self.banks[(ch.0 & 0x0F) as usize].map(U14::from);
+                Midi2Message::ProgramChange(ch, program, bank)
+            }
+            Message::ChannelMode(ch, mode) => {
+                let (control, value) = mode.into();
+                Midi2Message::ControlChange(ch, control, up7(value))
+            }
+            Message::ControlChange(ch, control, value) => return self.control_change(ch, control, value),
+            _ => return None,
+        })
+    }
+
+    fn control_change(&mut self, channel: Channel, control: Control, value: U7) -> Option<Midi2Message> {
+        let bank = &mut self.banks[(channel.0 & 0x0F) as usize];
+        match control.0 {
+            BANK_SELECT_MSB => {
+                *bank = Some((bank.map(|(lsb, _)| lsb).unwrap_or(U7(0)), value));
+                None
+            }
+            BANK_SELECT_LSB => {
+                *bank = Some((value, bank.map(|(_, msb)| msb).unwrap_or(U7(0))));
+                None
+            }
+            _ if ParameterDecoder::is_parameter_control(control) => {
+                let (ch, change) = self.parameters.advance(Message::ControlChange(channel, control, value))?;
+                let value = scale_up(change.value.0 as u32, 14, 32);
+                Some(match change.kind {
+                    ParameterKind::Rpn => Midi2Message::RegisteredController(ch, change.param, value),
+                    ParameterKind::Nrpn => Midi2Message::AssignableController(ch, change.param, value),
+                })
+            }
+            _ => Some(Midi2Message::ControlChange(channel, control, scale_up(value.0 as u32, 7, 32))),
+        }
+    }
+}
+
+/// Translate a MIDI 2.0 Channel Voice message to MIDI 1.0 messages
+/// Per-note controllers, per-note pitch bend, per-note management and relative controllers have no MIDI 1.0 equivalent and yield no message
+pub fn to_midi1(message: Midi2Message) -> Vec<Message, 4> {
+    let down7 = |value: u32| U7::cull(scale_down(value, 32, 7) as u8);
+    let mut messages = Vec::new();
+    match message {
+        Midi2Message::NoteOff(ch, note, velocity, _) => {
+            let _ = messages.push(Message::NoteOff(ch, note, U7::cull(scale_down(velocity as u32, 16, 7) as u8)));
+        }
+        Midi2Message::NoteOn(ch, note, velocity, _) => {
+            // zero velocity would mean Note Off
+            let velocity = (scale_down(velocity as u32, 16, 7) as u8).max(1);
+            let _ = messages.push(Message::NoteOn(ch, note, U7::cull(velocity)));
+        }
+        Midi2Message::NotePressure(ch, note, pressure) => {
+            let _ = messages.push(Message::NotePressure(ch, note, down7(pressure)));
+        }
+        Midi2Message::ChannelPressure(ch, pressure) => {
+            let _ = messages.push(Message::ChannelPressure(ch, down7(pressure)));
+        }
+        Midi2Message::PitchBend(ch, bend) => {
+            let _ = messages.push(Message::PitchBend(ch, U14::cull(scale_down(bend, 32, 14) as u16)));
+        }
+        Midi2Message::ControlChange(ch, control, value) => {
+            let value = down7(value);
+            let message = match ChannelMode::try_from((control, value)) {
+                Ok(mode) => Message::ChannelMode(ch, mode),
+                Err(_) => Message::ControlChange(ch, control, value),
+            };
+            let _ = messages.push(message);
+        }
+        Midi2Message::ProgramChange(ch, program, bank) => {
+            if let Some(bank) = bank {
+                let (lsb, msb) = bank.into();
+                let _ = messages.push(Message::ControlChange(ch, U7(BANK_SELECT_MSB), msb));
+                let _ = messages.push(Message::ControlChange(ch, U7(BANK_SELECT_LSB), lsb));
+            }
+            let _ = messages.push(Message::ProgramChange(ch, program));
+        }
+        Midi2Message::RegisteredController(ch, param, value) | Midi2Message::AssignableController(ch, param, value) => {
+            let value = U14::cull(scale_down(value, 32, 14) as u16);
+            let change = match message {
+                Midi2Message::RegisteredController(..) => ParameterChange::rpn(param, value),
+                _ => ParameterChange::nrpn(param, value),
+            };
+            messages.extend(change.encode(ch, false));
+        }
+        _ => {}
+    }
+    messages
+}
+
+/// Splits a sysex body into Sysex7 UMP payloads
+/// SYSEX_START and SYSEX_END markers are stripped if present
+#[derive(Debug, Clone)]
+pub struct Sysex7Packets<'a> {
+    body: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> Sysex7Packets<'a> {
+    pub fn new(body: &'a [u8]) -> Self {
+        let body = body.strip_prefix(&[SYSEX_START]).unwrap_or(body);
+        let body = body.strip_suffix(&[SYSEX_END]).unwrap_or(body);
+        Sysex7Packets { body, pos: 0, done: false }
+    }
+}
+
+impl<'a> Iterator for Sysex7Packets<'a> {
+    type Item = Sysex7;
+
+    fn next(&mut self) -> Option<Sysex7> {
+        if self.done {
+            return None;
+        }
+        let remaining = self.body.len() - self.pos;
+        let len = remaining.min(6);
+        let status = match (self.pos == 0, len == remaining) {
+            (true, true) => SysexStatus::Complete,
+            (true, false) => SysexStatus::Start,
+            (false, false) => SysexStatus::Continue,
+            (false, true) => SysexStatus::End,
+        };
+        let sysex = Sysex7::new(status, &self.body[self.pos..self.pos + len]).ok()?;
+        self.pos += len;
+        self.done = self.pos == self.body.len();
+        Some(sysex)
+    }
+}
+
+/// Collects Sysex7 UMP payloads into complete sysex bodies of up to N bytes
+#[derive(Debug, Default)]
+pub struct Sysex7Assembler<const N: usize> {
+    receiving: bool,
+    body: Vec<u8, N>,
+}
+
+impl<const N: usize> Sysex7Assembler<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop any partially received sysex
+    pub fn reset(&mut self) {
+        self.receiving = false;
+        self.body.clear();
+    }
+
+    /// Push next Sysex7 payload
+    /// returns:
+    /// - Ok(None) if sysex is incomplete
+    /// - Ok(Some(body)) if sysex is complete, body _excludes_ SYSEX_START and SYSEX_END markers
+    /// - Err(SysexOutOfBounds) if body exceeds N bytes, partial body is dropped
+    /// - Err(SysexInterrupted) if a new sysex started before the previous one ended
+    pub fn advance(&mut self, sysex: Sysex7) -> Result<Option<Vec<u8, N>>, MidiError> {
+        let interrupted = match sysex.status {
+            SysexStatus::Complete | SysexStatus::Start => {
+                let interrupted = self.receiving;
+                self.reset();
+                self.receiving = true;
+                interrupted
+            }
+            SysexStatus::Continue | SysexStatus::End if !self.receiving => return Ok(None),
+            _ => false,
+        };
+        if self.body.extend_from_slice(sysex.data()).is_err() {
+            self.reset();
+            return Err(MidiError::SysexOutOfBounds);
+        }
+        if interrupted {
+            return Err(MidiError::SysexInterrupted);
+        }
+        if matches!(sysex.status, SysexStatus::Complete | SysexStatus::End) {
+            self.receiving = false;
+            return Ok(Some(core::mem::take(&mut self.body)));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, Note};
+
+    #[test]
+    fn scaling() {
+        assert_eq!(scale_up(0, 7, 16), 0);
+        assert_eq!(scale_up(64, 7, 16), 0x8000);
+        assert_eq!(scale_up(127, 7, 16), 0xFFFF);
+        assert_eq!(scale_up(127, 7, 32), 0xFFFF_FFFF);
+        assert_eq!(scale_up(0x2000, 14, 32), 0x8000_0000);
+        assert_eq!(scale_up(0x3FFF, 14, 32), 0xFFFF_FFFF);
+        assert_eq!(scale_down(0xFFFF, 16, 7), 127);
+        for value in 0..128 {
+            assert_eq!(scale_down(scale_up(value, 7, 32), 32, 7), value);
+        }
+        assert_eq!(scale_up(0x1234, 16, 7), 0x1234);
+        assert_eq!(scale_up(5, 0, 7), 5);
+        assert_eq!(scale_down(100, 7, 16), 100);
+        assert_eq!(scale_down(0xFFFF_FFFF, 40, 0), 0);
+    }
+
+    #[test]
+    fn notes() {
+        let mut translator = Midi1Translator::new();
+        let off = translator.advance(Message::NoteOn(channel(1), Note::C4, U7(0))).unwrap();
+        assert_eq!(off, Midi2Message::NoteOff(channel(1), Note::C4, 0x8000, NoteAttribute::default()));
+        let on = to_midi1(Midi2Message::NoteOn(channel(1), Note::C4, 0x0100, NoteAttribute::default()));
+        assert!(matches!(on[0], Message::NoteOn(_, Note::C4, U7(1))));
+    }
+
+    #[test]
+    fn program_with_bank() {
+        let mut translator = Midi1Translator::new();
+        let ch = channel(2);
+        for message in to_midi1(Midi2Message::ProgramChange(ch, U7(9), Some(U14(0x0203)))) {
+            if let Some(translated) = translator.advance(message) {
+                assert_eq!(translated, Midi2Message::ProgramChange(ch, U7(9), Some(U14(0x0203))));
+            }
+        }
+    }
+
+    #[test]
+    fn registered_controller() {
+        let mut translator = Midi1Translator::new();
+        let ch = channel(3);
+        let rpn = Midi2Message::RegisteredController(ch, ParameterChange::PITCH_BEND_SENSITIVITY, scale_up(0x0185, 14, 32));
+        let messages = to_midi1(rpn);
+        assert_eq!(messages.len(), 4);
+        let translated: Vec<_, 4> = messages.into_iter().filter_map(|message| translator.advance(message)).collect();
+        // data entry MSB is emitted first, with LSB cleared
+        let msb_only = Midi2Message::RegisteredController(ch, ParameterChange::PITCH_BEND_SENSITIVITY, scale_up(0x0180, 14, 32));
+        assert_eq!(&translated[..], &[msb_only, rpn]);
+    }
+
+    #[test]
+    fn sysex7() {
+        let body = [SYSEX_START, 1, 2, 3, 4, 5, 6, 7, 8, SYSEX_END];
+        let mut assembler = Sysex7Assembler::<16>::new();
+        let mut complete = None;
+        let mut count = 0;
+        for sysex in Sysex7Packets::new(&body) {
+            count += 1;
+            complete = assembler.advance(sysex).unwrap();
+        }
+        assert_eq!(count, 2);
+        assert_eq!(complete.unwrap().as_slice(), &body[1..9]);
+
+        let empty: heapless::Vec<_, 2> = Sysex7Packets::new(&[]).collect();
+        assert_eq!(empty[0].status, SysexStatus::Complete);
+    }
+}
@@ -3,6 +3,7 @@ use crate::{MidiError, Cull, Fill};
 
 /// A primitive value that can be from 0-0x7F
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct U4(pub u8);
 
 impl TryFrom<u8> for U4 {
@@ -0,0 +1,573 @@
+//! MIDI 2.0 Universal MIDI Packet (UMP) definitions
+//! A UMP is made of one to four 32-bit words, the top nibble of the first word giving the message type
+
+use crate::status::{is_channel_status, status_byte, Status, SYSEX_START};
+use crate::{Channel, Control, Cull, Message, MidiError, Note, Program, U14, U4, U7};
+use core::convert::TryFrom;
+use num_enum::UnsafeFromPrimitive;
+
+/// UMP group, each of the 16 groups carries 16 channels
+pub type Group = U4;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, UnsafeFromPrimitive)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum MessageType {
+    Utility = 0x0,
+    System = 0x1,
+    /// MIDI 1.0 Channel Voice
+    Midi1 = 0x2,
+    /// Sysex7
+    Data64 = 0x3,
+    /// MIDI 2.0 Channel Voice
+    Midi2 = 0x4,
+    /// Sysex8 and Mixed Data Set
+    Data128 = 0x5,
+    Reserved6 = 0x6,
+    Reserved7 = 0x7,
+    Reserved8 = 0x8,
+    Reserved9 = 0x9,
+    ReservedA = 0xA,
+    ReservedB = 0xB,
+    ReservedC = 0xC,
+    FlexData = 0xD,
+    ReservedE = 0xE,
+    Stream = 0xF,
+}
+
+impl From<u8> for MessageType {
+    fn from(nibble: u8) -> Self {
+        unsafe { MessageType::from_unchecked(nibble & 0x0F) }
+    }
+}
+
+impl MessageType {
+    /// Number of 32-bit words in UMPs of this type
+    pub fn word_len(&self) -> usize {
+        match self {
+            MessageType::Utility | MessageType::System | MessageType::Midi1 | MessageType::Reserved6 | MessageType::Reserved7 => 1,
+            MessageType::Data64 | MessageType::Midi2 | MessageType::Reserved8 | MessageType::Reserved9 | MessageType::ReservedA => 2,
+            MessageType::ReservedB | MessageType::ReservedC => 3,
+            MessageType::Data128 | MessageType::FlexData | MessageType::ReservedE | MessageType::Stream => 4,
+        }
+    }
+}
+
+/// Raw Universal MIDI Packet
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ump {
+    words: [u32; 4],
+}
+
+impl Ump {
+    /// Number of words must match message type
+    pub fn from_words(words: &[u32]) -> Result<Self, MidiError> {
+        let first = words.first().ok_or(MidiError::TruncatedMessage)?;
+        let len = MessageType::from((first >> 28) as u8).word_len();
+        if words.len() != len {
+            return Err(MidiError::TruncatedMessage);
+        }
+        let mut ump = Ump::default();
+        ump.words[..len].copy_from_slice(words);
+        Ok(ump)
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        MessageType::from((self.words[0] >> 28) as u8)
+    }
+
+    /// Meaningless for Utility and Stream messages
+    pub fn group(&self) -> Group {
+        U4::cull((self.words[0] >> 24) as u8)
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words[..self.message_type().word_len()]
+    }
+
+    fn status(&self) -> u8 {
+        (self.words[0] >> 20) as u8 & 0x0F
+    }
+
+    fn nibble(&self) -> u8 {
+        (self.words[0] >> 16) as u8 & 0x0F
+    }
+
+    fn byte(&self, index: usize) -> u8 {
+        (self.words[index / 4] >> (24 - (index % 4) * 8)) as u8
+    }
+}
+
+/// Assembles UMP words into packets
+#[derive(Debug, Default)]
+pub struct UmpParser {
+    words: [u32; 4],
+    len: usize,
+}
+
+impl UmpParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push next word
+    /// returns:
+    /// - None if packet is incomplete
+    /// - Some(ump) if packet is complete
+    pub fn advance(&mut self, word: u32) -> Option<Ump> {
+        self.words[self.len] = word;
+        self.len += 1;
+        if self.len < MessageType::from((self.words[0] >> 28) as u8).word_len() {
+            return None;
+        }
+        let ump = Ump { words: self.words };
+        *self = Self::default();
+        Some(ump)
+    }
+}
+
+/// Position of a sysex packet in the complete sysex message
+#[derive(Copy, Clone, Debug, Eq, PartialEq, UnsafeFromPrimitive)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SysexStatus {
+    Complete = 0,
+    Start = 1,
+    Continue = 2,
+    End = 3,
+}
+
+impl TryFrom<u8> for SysexStatus {
+    type Error = MidiError;
+
+    fn try_from(status: u8) -> Result<Self, Self::Error> {
+        if status > SysexStatus::End as u8 {
+            return Err(MidiError::InvalidStatus(status));
+        }
+        Ok(unsafe { SysexStatus::from_unchecked(status) })
+    }
+}
+
+/// Up to 6 bytes of 7-bit sysex data, without SYSEX_START and SYSEX_END
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sysex7 {
+    pub status: SysexStatus,
+    len: u8,
+    bytes: [u8; 6],
+}
+
+impl Sysex7 {
+    pub fn new(status: SysexStatus, data: &[u8]) -> Result<Self, MidiError> {
+        let mut bytes = [0; 6];
+        bytes.get_mut(..data.len()).ok_or(MidiError::SysexOutOfBounds)?.copy_from_slice(data);
+        Ok(Sysex7 { status, len: data.len() as u8, bytes })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Up to 13 bytes of 8-bit sysex data, tagged with a stream id
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sysex8 {
+    pub status: SysexStatus,
+    pub stream_id: u8,
+    len: u8,
+    bytes: [u8; 13],
+}
+
+impl Sysex8 {
+    pub fn new(status: SysexStatus, stream_id: u8, data: &[u8]) -> Result<Self, MidiError> {
+        let mut bytes = [0; 13];
+        bytes.get_mut(..data.len()).ok_or(MidiError::SysexOutOfBounds)?.copy_from_slice(data);
+        Ok(Sysex8 { status, stream_id, len: data.len() as u8, bytes })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Data128 {
+    Sysex8(Sysex8),
+    /// Mixed Data Set id, raw 14 bytes
+    MixedDataSetHeader(u8, [u8; 14]),
+    MixedDataSetPayload(u8, [u8; 14]),
+}
+
+/// Jitter Reduction and timing messages
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Utility {
+    Noop,
+    JrClock(u16),
+    JrTimestamp(u16),
+    DeltaClockstampTicksPerQuarter(u16),
+    /// 20 bits of ticks since last event
+    DeltaClockstamp(u32),
+}
+
+/// MIDI 2.0 Note On/Off attribute, type 0 means no attribute
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoteAttribute {
+    pub kind: u8,
+    pub data: u16,
+}
+
+/// MIDI 2.0 Channel Voice messages
+/// Registered and Assignable controllers are addressed as bank (MSB) and index (LSB), same as RPN/NRPN
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Midi2Message {
+    /// Channel, note, controller index, value
+    RegisteredPerNoteController(Channel, Note, u8, u32),
+    AssignablePerNoteController(Channel, Note, u8, u32),
+    /// Channel, bank & index, value
+    RegisteredController(Channel, U14, u32),
+    AssignableController(Channel, U14, u32),
+    RelativeRegisteredController(Channel, U14, i32),
+    RelativeAssignableController(Channel, U14, i32),
+    PerNotePitchBend(Channel, Note, u32),
+    /// Channel, note, velocity, attribute
+    NoteOff(Channel, Note, u16, NoteAttribute),
+    NoteOn(Channel, Note, u16, NoteAttribute),
+    NotePressure(Channel, Note, u32),
+    ControlChange(Channel, Control, u32),
+    /// Channel, program, bank MSB & LSB if bank is valid
+    ProgramChange(Channel, Program, Option<U14>),
+    ChannelPressure(Channel, u32),
+    PitchBend(Channel, u32),
+    /// Channel, note, detach per-note controllers, reset per-note controllers
+    PerNoteManagement(Channel, Note, bool, bool),
+}
+
+/// Flex Data messages (text, tempo, chords, etc.), fields are raw
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlexData {
+    pub form: u8,
+    pub address: u8,
+    pub channel: Channel,
+    pub status_bank: u8,
+    pub status: u8,
+    pub data: [u32; 3],
+}
+
+/// UMP Stream messages (endpoint discovery, function blocks, etc.), fields are raw
+/// The first data word holds the lower 16 bits of the first UMP word
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stream {
+    pub form: u8,
+    pub status: u16,
+    pub data: [u32; 4],
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UmpMessage {
+    Utility(Utility),
+    /// System Common and System Realtime
+    System(Group, Message),
+    /// MIDI 1.0 Channel Voice
+    Midi1(Group, Message),
+    Sysex7(Group, Sysex7),
+    /// MIDI 2.0 Channel Voice
+    Midi2(Group, Midi2Message),
+    Data128(Group, Data128),
+    FlexData(Group, FlexData),
+    Stream(Stream),
+    Reserved(Ump),
+}
+
+impl TryFrom<Ump> for UmpMessage {
+    type Error = MidiError;
+
+    fn try_from(ump: Ump) -> Result<Self, Self::Error> {
+        let group = ump.group();
+        let w = ump.words;
+        Ok(match ump.message_type() {
+            MessageType::Utility => UmpMessage::Utility(match ump.status() {
+                0x0 => Utility::Noop,
+                0x1 => Utility::JrClock(w[0] as u16),
+                0x2 => Utility::JrTimestamp(w[0] as u16),
+                0x3 => Utility::DeltaClockstampTicksPerQuarter(w[0] as u16),
+                0x4 => Utility::DeltaClockstamp(w[0] & 0x000F_FFFF),
+                _ => return Err(MidiError::BadUmp(ump)),
+            }),
+            MessageType::System | MessageType::Midi1 => {
+                let bytes = [ump.byte(1), ump.byte(2), ump.byte(3)];
+                let status = Status::try_from(bytes[0])?;
+                let channel_voice = is_channel_status(bytes[0]);
+                if channel_voice != (ump.message_type() == MessageType::Midi1) || status == Status::SysexStart {
+                    return Err(MidiError::BadUmp(ump));
+                }
+                let message = Message::try_from(&bytes[..status.expected_len() as usize])?;
+                if channel_voice {
+                    UmpMessage::Midi1(group, message)
+                } else {
+                    UmpMessage::System(group, message)
+                }
+            }
+            MessageType::Data64 => {
+                let len = ump.nibble() as usize;
+                let bytes = [ump.byte(2), ump.byte(3), ump.byte(4), ump.byte(5), ump.byte(6), ump.byte(7)];
+                let data = bytes.get(..len).ok_or(MidiError::BadUmp(ump))?;
+                UmpMessage::Sysex7(group, Sysex7::new(SysexStatus::try_from(ump.status())?, data)?)
+            }
+            MessageType::Midi2 => UmpMessage::Midi2(group, decode_midi2(&ump)?),
+            MessageType::Data128 => {
+                let mut bytes = [0; 14];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = ump.byte(i + 2);
+                }
+                UmpMessage::Data128(group, match ump.status() {
+                    status @ 0..=3 => {
+                        // byte count includes stream id
+                        let len = ump.nibble() as usize;
+                        let data = bytes[1..].get(..len.saturating_sub(1)).ok_or(MidiError::BadUmp(ump))?;
+                        Data128::Sysex8(Sysex8::new(SysexStatus::try_from(status)?, bytes[0], data)?)
+                    }
+                    0x8 => Data128::MixedDataSetHeader(ump.nibble(), bytes),
+                    0x9 => Data128::MixedDataSetPayload(ump.nibble(), bytes),
+                    _ => return Err(MidiError::BadUmp(ump)),
+                })
+            }
+            MessageType::FlexData => UmpMessage::FlexData(group, FlexData {
+                form: (w[0] >> 22) as u8 & 0x03,
+                address: (w[0] >> 20) as u8 & 0x03,
+                channel: Channel(ump.nibble()),
+                status_bank: ump.byte(2),
+                status: ump.byte(3),
+                data: [w[1], w[2], w[3]],
+            }),
+            MessageType::Stream => UmpMessage::Stream(Stream {
+                form: (w[0] >> 26) as u8 & 0x03,
+                status: (w[0] >> 16) as u16 & 0x03FF,
+                data: [w[0] & 0xFFFF, w[1], w[2], w[3]],
+            }),
+            _ => UmpMessage::Reserved(ump),
+        })
+    }
+}
+
+fn decode_midi2(ump: &Ump) -> Result<Midi2Message, MidiError> {
+    let channel = Channel(ump.nibble());
+    let (b2, b3) = (ump.byte(2), ump.byte(3));
+    let note = || Note::try_from(b2 & 0x7F);
+    let controller = U14::from((U7::cull(b3), U7::cull(b2)));
+    let data = ump.words[1];
+    Ok(match ump.status() {
+        0x0 => Midi2Message::RegisteredPerNoteController(channel, note()?, b3, data),
+        0x1 => Midi2Message::AssignablePerNoteController(channel, note()?, b3, data),
+        0x2 => Midi2Message::RegisteredController(channel, controller, data),
+        0x3 => Midi2Message::AssignableController(channel, controller, data),
+        0x4 => Midi2Message::RelativeRegisteredController(channel, controller, data as i32),
+        0x5 => Midi2Message::RelativeAssignableController(channel, controller, data as i32),
+        0x6 => Midi2Message::PerNotePitchBend(channel, note()?, data),
+        0x8 | 0x9 => {
+            let velocity = (data >> 16) as u16;
+            let attribute = NoteAttribute { kind: b3, data: data as u16 };
+            if ump.status() == 0x8 {
+                Midi2Message::NoteOff(channel, note()?, velocity, attribute)
+            } else {
+                Midi2Message::NoteOn(channel, note()?, velocity, attribute)
+            }
+        }
+        0xA => Midi2Message::NotePressure(channel, note()?, data),
+        0xB => Midi2Message::ControlChange(channel, U7::cull(b2), data),
+        0xC => {
+            let bank = (b3 & 0x01 != 0).then(|| U14::from((U7::cull(data as u8), U7::cull((data >> 8) as u8))));
+            Midi2Message::ProgramChange(channel, U7::cull((data >> 24) as u8), bank)
+        }
+        0xD => Midi2Message::ChannelPressure(channel, data),
+        0xE => Midi2Message::PitchBend(channel, data),
+        0xF => Midi2Message::PerNoteManagement(channel, note()?, b3 & 0x02 != 0, b3 & 0x01 != 0),
+        _ => return Err(MidiError::BadUmp(*ump)),
+    })
+}
+
+fn header(message_type: MessageType, group: Group, status: u8, nibble: u8) -> u32 {
+    (message_type as u32) << 28 | (group.0 as u32 & 0x0F) << 24 | (status as u32 & 0x0F) << 20 | (nibble as u32 & 0x0F) << 16
+}
+
+fn pack(bytes: &[u8]) -> u32 {
+    bytes.iter().enumerate().fold(0, |word, (i, byte)| word | (*byte as u32) << (24 - i * 8))
+}
+
+fn encode_midi2(group: Group, message: Midi2Message) -> [u32; 2] {
+    let header = |status: u8, channel: Channel, b2: u8, b3: u8| header(MessageType::Midi2, group, status, channel.0) | (b2 as u32) << 8 | b3 as u32;
+    let controller = |index: U14| {
+        let (lsb, msb) = index.into();
+        (u8::from(msb), u8::from(lsb))
+    };
+    match message {
+        Midi2Message::RegisteredPerNoteController(ch, note, index, data) => [header(0x0, ch, note as u8, index), data],
+        Midi2Message::AssignablePerNoteController(ch, note, index, data) => [header(0x1, ch, note as u8, index), data],
+        Midi2Message::RegisteredController(ch, index, data) => {
+            let (bank, index) = controller(index);
+            [header(0x2, ch, bank, index), data]
+        }
+        Midi2Message::AssignableController(ch, index, data) => {
+            let (bank, index) = controller(index);
+            [header(0x3, ch, bank, index), data]
+        }
+        Midi2Message::RelativeRegisteredController(ch, index, data) => {
+            let (bank, index) = controller(index);
+            [header(0x4, ch, bank, index), data as u32]
+        }
+        Midi2Message::RelativeAssignableController(ch, index, data) => {
+            let (bank, index) = controller(index);
+            [header(0x5, ch, bank, index), data as u32]
+        }
+        Midi2Message::PerNotePitchBend(ch, note, data) => [header(0x6, ch, note as u8, 0), data],
+        Midi2Message::NoteOff(ch, note, velocity, attr) => [header(0x8, ch, note as u8, attr.kind), (velocity as u32) << 16 | attr.data as u32],
+        Midi2Message::NoteOn(ch, note, velocity, attr) => [header(0x9, ch, note as u8, attr.kind), (velocity as u32) << 16 | attr.data as u32],
+        Midi2Message::NotePressure(ch, note, data) => [header(0xA, ch, note as u8, 0), data],
+        Midi2Message::ControlChange(ch, control, data) => [header(0xB, ch, control.0, 0), data],
+        Midi2Message::ProgramChange(ch, program, bank) => {
+            let (flags, (msb, lsb)) = match bank {
+                Some(bank) => (0x01, controller(bank)),
+                None => (0x00, (0, 0)),
+            };
+            [header(0xC, ch, 0, flags), pack(&[program.0, 0, msb, lsb])]
+        }
+        Midi2Message::ChannelPressure(ch, data) => [header(0xD, ch, 0, 0), data],
+        Midi2Message::PitchBend(ch, data) => [header(0xE, ch, 0, 0), data],
+        Midi2Message::PerNoteManagement(ch, note, detach, reset) => [header(0xF, ch, note as u8, (detach as u8) << 1 | reset as u8), 0],
+    }
+}
+
+impl TryFrom<UmpMessage> for Ump {
+    type Error = MidiError;
+
+    fn try_from(ump_message: UmpMessage) -> Result<Self, Self::Error> {
+        let mut words = [0; 4];
+        match ump_message {
+            UmpMessage::Utility(utility) => {
+                words[0] = match utility {
+                    Utility::Noop => 0,
+                    Utility::JrClock(time) => 0x1 << 20 | time as u32,
+                    Utility::JrTimestamp(time) => 0x2 << 20 | time as u32,
+                    Utility::DeltaClockstampTicksPerQuarter(ticks) => 0x3 << 20 | ticks as u32,
+                    Utility::DeltaClockstamp(ticks) => 0x4 << 20 | (ticks & 0x000F_FFFF),
+                };
+            }
+            UmpMessage::System(group, message) | UmpMessage::Midi1(group, message) => {
+                let message_type = match ump_message {
+                    UmpMessage::Midi1(..) => MessageType::Midi1,
+                    _ => MessageType::System,
+                };
+                let status = status_byte(&message).ok_or(MidiError::InvalidStatus(SYSEX_START))?;
+                if is_channel_status(status) != (message_type == MessageType::Midi1) {
+                    return Err(MidiError::InvalidStatus(status));
+                }
+                let mut bytes = [0; 3];
+                message.encode_into(&mut bytes);
+                words[0] = (message_type as u32) << 28 | (group.0 as u32 & 0x0F) << 24 | pack(&bytes) >> 8;
+            }
+            UmpMessage::Sysex7(group, sysex) => {
+                let data = sysex.data();
+                words[0] = header(MessageType::Data64, group, sysex.status as u8, data.len() as u8) | pack(&sysex.bytes[..2]) >> 16;
+                words[1] = pack(&sysex.bytes[2..]);
+            }
+            UmpMessage::Midi2(group, message) => {
+                let [w0, w1] = encode_midi2(group, message);
+                words[0] = w0;
+                words[1] = w1;
+            }
+            UmpMessage::Data128(group, data) => {
+                let (status, nibble, bytes) = match data {
+                    Data128::Sysex8(sysex) => {
+                        let mut bytes = [0; 14];
+                        bytes[0] = sysex.stream_id;
+                        bytes[1..].copy_from_slice(&sysex.bytes);
+                        (sysex.status as u8, sysex.len + 1, bytes)
+                    }
+                    Data128::MixedDataSetHeader(id, bytes) => (0x8, id, bytes),
+                    Data128::MixedDataSetPayload(id, bytes) => (0x9, id, bytes),
+                };
+                words[0] = header(MessageType::Data128, group, status, nibble) | pack(&bytes[..2]) >> 16;
+                words[1] = pack(&bytes[2..6]);
+                words[2] = pack(&bytes[6..10]);
+                words[3] = pack(&bytes[10..14]);
+            }
+            UmpMessage::FlexData(group, flex) => {
+                words[0] = (MessageType::FlexData as u32) << 28 | (group.0 as u32 & 0x0F) << 24
+                    | (flex.form as u32 & 0x03) << 22 | (flex.address as u32 & 0x03) << 20 | (flex.channel.0 as u32 & 0x0F) << 16
+                    | (flex.status_bank as u32) << 8 | flex.status as u32;
+                words[1..].copy_from_slice(&flex.data);
+            }
+            UmpMessage::Stream(stream) => {
+                words[0] = (MessageType::Stream as u32) << 28 | (stream.form as u32 & 0x03) << 26
+                    | (stream.status as u32 & 0x03FF) << 16 | (stream.data[0] & 0xFFFF);
+                words[1..].copy_from_slice(&stream.data[1..]);
+            }
+            UmpMessage::Reserved(ump) => return Ok(ump),
+        }
+        Ok(Ump { words })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, note_on};
+
+    fn round_trip(message: UmpMessage) -> UmpMessage {
+        let ump = Ump::try_from(message).unwrap();
+        let mut parser = UmpParser::new();
+        let mut parsed = None;
+        for word in ump.words() {
+            parsed = parser.advance(*word);
+        }
+        UmpMessage::try_from(parsed.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn midi1_channel_voice() {
+        let message = UmpMessage::Midi1(U4(3), note_on(channel(2), Note::C4, 100).unwrap());
+        let ump = Ump::try_from(message).unwrap();
+        assert_eq!(ump.words(), &[0x2391_3C64]);
+        assert!(matches!(round_trip(message), UmpMessage::Midi1(U4(3), Message::NoteOn(Channel(1), Note::C4, U7(100)))));
+    }
+
+    #[test]
+    fn system() {
+        assert!(matches!(round_trip(UmpMessage::System(U4(0), Message::TimingClock)), UmpMessage::System(_, Message::TimingClock)));
+        assert!(Ump::try_from(UmpMessage::System(U4(0), note_on(channel(1), Note::C4, 1).unwrap())).is_err());
+    }
+
+    #[test]
+    fn midi2_channel_voice() {
+        let note = Midi2Message::NoteOn(Channel(4), Note::A4, 0xC000, NoteAttribute { kind: 3, data: 0x1234 });
+        let ump = Ump::try_from(UmpMessage::Midi2(U4(1), note)).unwrap();
+        assert_eq!(ump.words(), &[0x4194_4503, 0xC000_1234]);
+        assert!(matches!(round_trip(UmpMessage::Midi2(U4(1), note)), UmpMessage::Midi2(U4(1), decoded) if decoded == note));
+
+        let program = Midi2Message::ProgramChange(Channel(0), U7(5), Some(U14(0x0102)));
+        assert!(matches!(round_trip(UmpMessage::Midi2(U4(0), program)), UmpMessage::Midi2(_, decoded) if decoded == program));
+    }
+
+    #[test]
+    fn sysex7() {
+        let sysex = Sysex7::new(SysexStatus::Start, &[1, 2, 3, 4, 5]).unwrap();
+        let ump = Ump::try_from(UmpMessage::Sysex7(U4(0), sysex)).unwrap();
+        assert_eq!(ump.words(), &[0x3015_0102, 0x0304_0500]);
+        assert!(matches!(round_trip(UmpMessage::Sysex7(U4(0), sysex)), UmpMessage::Sysex7(_, decoded) if decoded == sysex));
+    }
+
+    #[test]
+    fn sysex8() {
+        let sysex = Sysex8::new(SysexStatus::Complete, 7, &[0x80, 0x81, 0x82]).unwrap();
+        let data = Data128::Sysex8(sysex);
+        assert!(matches!(round_trip(UmpMessage::Data128(U4(2), data)), UmpMessage::Data128(U4(2), decoded) if decoded == data));
+    }
+}
@@ -0,0 +1,158 @@
+//! Universal System Exclusive messages
+//! Bodies exclude SYSEX_START and SYSEX_END: sub-id (7E or 7F), device id, sub-id #1, sub-id #2, data...
+
+use crate::{MidiError, U14, U7};
+use heapless::Vec;
+
+pub const NON_REALTIME: u8 = 0x7E;
+pub const REALTIME: u8 = 0x7F;
+
+/// Device id addressing every device
+pub const ALL_CALL: U7 = U7(0x7F);
+
+/// Manufacturer's System Exclusive id, either a single byte or 0x00 followed by two bytes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ManufacturerId {
+    Short(u8),
+    Extended(u8, u8),
+}
+
+impl ManufacturerId {
+    /// Id reserved for non-commercial use
+    pub const NON_COMMERCIAL: ManufacturerId = ManufacturerId::Short(0x7D);
+
+    /// Read a one or three byte id, returns id and remaining bytes
+    pub fn read(bytes: &[u8]) -> Result<(Self, &[u8]), MidiError> {
+        match bytes {
+            [0, b1, b2, rest @ ..] => Ok((ManufacturerId::Extended(*b1, *b2), rest)),
+            [0, ..] | [] => Err(MidiError::TruncatedMessage),
+            [id, rest @ ..] => Ok((ManufacturerId::Short(*id), rest)),
+        }
+    }
+
+    /// Write the one or three byte id
+    pub fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        match *self {
+            ManufacturerId::Short(id) => push(buf, &[id]),
+            ManufacturerId::Extended(b1, b2) => push(buf, &[0, b1, b2]),
+        }
+    }
+
+    /// Always three bytes, short ids are padded with zeroes
+    pub fn to_bytes(&self) -> [u8; 3] {
+        match *self {
+            ManufacturerId::Short(id) => [id, 0, 0],
+            ManufacturerId::Extended(b1, b2) => [0, b1, b2],
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Self {
+        match bytes {
+            [0, b1, b2] => ManufacturerId::Extended(b1, b2),
+            [id, ..] => ManufacturerId::Short(id),
+        }
+    }
+}
+
+/// Manufacturer, family, model and software revision of a device
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceIdentity {
+    pub manufacturer: ManufacturerId,
+    pub family: U14,
+    pub model: U14,
+    pub version: [u8; 4],
+}
+
+impl DeviceIdentity {
+    /// Read family, model and version (8 bytes), returns remaining bytes
+    pub(crate) fn read_details(manufacturer: ManufacturerId, bytes: &[u8]) -> Result<(Self, &[u8]), MidiError> {
+        let details = bytes.get(..8).ok_or(MidiError::TruncatedMessage)?;
+        Ok((
+            DeviceIdentity {
+                manufacturer,
+                family: U14::from((U7(details[0] & 0x7F), U7(details[1] & 0x7F))),
+                model: U14::from((U7(details[2] & 0x7F), U7(details[3] & 0x7F))),
+                version: [details[4], details[5], details[6], details[7]],
+            },
+            &bytes[8..],
+        ))
+    }
+
+    /// Write family, model and version (8 bytes)
+    pub(crate) fn write_details<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let (family_lsb, family_msb) = self.family.into();
+        let (model_lsb, model_msb) = self.model.into();
+        push(buf, &[family_lsb.0, family_msb.0, model_lsb.0, model_msb.0])?;
+        push(buf, &self.version)
+    }
+}
+
+/// Leading bytes of a Universal System Exclusive body
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UniversalHeader {
+    pub realtime: bool,
+    /// Target device, or ALL_CALL
+    pub device_id: U7,
+    pub sub_id1: u8,
+    pub sub_id2: u8,
+}
+
+impl UniversalHeader {
+    pub fn non_realtime(device_id: U7, sub_id1: u8, sub_id2: u8) -> Self {
+        UniversalHeader { realtime: false, device_id, sub_id1, sub_id2 }
+    }
+
+    pub fn realtime(device_id: U7, sub_id1: u8, sub_id2: u8) -> Self {
+        UniversalHeader { realtime: true, device_id, sub_id1, sub_id2 }
+    }
+
+    /// Read header from sysex body, returns header and remaining data
+    pub fn read(body: &[u8]) -> Result<(Self, &[u8]), MidiError> {
+        match body {
+            [kind @ (NON_REALTIME | REALTIME), device_id, sub_id1, sub_id2, data @ ..] => Ok((
+                UniversalHeader {
+                    realtime: *kind == REALTIME,
+                    device_id: U7(device_id & 0x7F),
+                    sub_id1: *sub_id1,
+                    sub_id2: *sub_id2,
+                },
+                data,
+            )),
+            [NON_REALTIME | REALTIME, ..] => Err(MidiError::TruncatedMessage),
+            _ => Err(MidiError::InvalidSysex),
+        }
+    }
+
+    pub fn write<const N: usize>(&self, buf: &mut Vec<u8, N>) -> Result<(), MidiError> {
+        let kind = if self.realtime { REALTIME } else { NON_REALTIME };
+        push(buf, &[kind, self.device_id.0, self.sub_id1, self.sub_id2])
+    }
+
+    /// Returns true if message is addressed to device `device_id`, either directly or with ALL_CALL
+    pub fn is_for(&self, device_id: U7) -> bool {
+        self.device_id == device_id || self.device_id == ALL_CALL
+    }
+}
+
+/// Append bytes to sysex body
+pub(crate) fn push<const N: usize>(buf: &mut Vec<u8, N>, bytes: &[u8]) -> Result<(), MidiError> {
+    buf.extend_from_slice(bytes).map_err(|_| MidiError::SysexOutOfBounds)
+}
+
+/// Read `len` 7-bit bytes as a little-endian value, returns value and remaining bytes
+pub(crate) fn read_7bit(bytes: &[u8], len: usize) -> Result<(u32, &[u8]), MidiError> {
+    let value = bytes.get(..len).ok_or(MidiError::TruncatedMessage)?;
+    let value = value.iter().rev().fold(0, |acc, byte| acc << 7 | (byte & 0x7F) as u32);
+    Ok((value, &bytes[len..]))
+}
+
+/// Write `value` as `len` 7-bit bytes, little-endian
+pub(crate) fn write_7bit<const N: usize>(buf: &mut Vec<u8, N>, value: u32, len: usize) -> Result<(), MidiError> {
+    for i in 0..len {
+        push(buf, &[(value >> (i * 7)) as u8 & 0x7F])?;
+    }
+    Ok(())
+}